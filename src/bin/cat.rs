@@ -2,21 +2,66 @@
 //! output in the same sequence.
 
 use clap::{App, Arg};
+use coreutils_rs::write_error;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::process;
-use std::str;
 
 fn main() {
     let matches = App::new("rust-cat")
         .version("0.1.0")
         .author("Jos V. <jos@josaphat.co>")
         .about("Rust clone of the cat utility. Concatenate FILE(s) to standard output.\n\nWith no FILE, or when FILE is -, read standard input.")
+        .arg(Arg::new("show_all")
+             .short('A')
+             .long("show-all")
+             .help("equivalent to -vET")
+             .takes_value(false))
+        .arg(Arg::new("number_nonblank")
+             .short('b')
+             .long("number-nonblank")
+             .help("number nonempty output lines, overrides -n")
+             .takes_value(false))
+        .arg(Arg::new("e")
+             .short('e')
+             .help("equivalent to -vE")
+             .takes_value(false))
+        .arg(Arg::new("show_ends")
+             .short('E')
+             .long("show-ends")
+             .help("display $ at end of each line")
+             .takes_value(false))
+        .arg(Arg::new("number")
+             .short('n')
+             .long("number")
+             .help("number all output lines")
+             .takes_value(false))
+        .arg(Arg::new("squeeze_blank")
+             .short('s')
+             .long("squeeze-blank")
+             .help("suppress repeated empty output lines")
+             .takes_value(false))
+        .arg(Arg::new("t")
+             .short('t')
+             .help("equivalent to -vT")
+             .takes_value(false))
+        .arg(Arg::new("show_tabs")
+             .short('T')
+             .long("show-tabs")
+             .help("display TAB characters as ^I")
+             .takes_value(false))
         .arg(Arg::new("unbuffered")
              .short('u')
              .help("Ignored (present for POSIX compatibility)")
              .takes_value(false))
+        .arg(Arg::new("show_nonprinting")
+             .short('v')
+             .long("show-nonprinting")
+             .help("use ^ and M- notation, except for LFD and TAB")
+             .takes_value(false))
         .arg(Arg::new("FILE")
              .help("")
              .required(false)
@@ -27,35 +72,366 @@ fn main() {
     // unwrap is fine here; FILE will have a default.
     let files: Vec<_> = matches.values_of("FILE").unwrap().collect();
 
+    let show_all = matches.is_present("show_all");
+    let number_nonblank = matches.is_present("number_nonblank");
+    let mut formatter = Formatter {
+        number: matches.is_present("number") && !number_nonblank,
+        number_nonblank,
+        squeeze_blank: matches.is_present("squeeze_blank"),
+        show_ends: show_all || matches.is_present("e") || matches.is_present("show_ends"),
+        show_tabs: show_all || matches.is_present("t") || matches.is_present("show_tabs"),
+        show_nonprinting: show_all
+            || matches.is_present("e")
+            || matches.is_present("t")
+            || matches.is_present("show_nonprinting"),
+        line_number: 0,
+        at_line_start: true,
+        blank_lines: 0,
+    };
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::with_capacity(BUF_SIZE, stdout.lock());
+
+    // Used to spot an input that is also our output. If stdout can't
+    // be examined there's nothing to compare against.
+    let out_stat = fstat(libc::STDOUT_FILENO).ok();
+
+    // A FILE that can't be read is reported and skipped; the rest
+    // are still printed, and we exit unsuccessfully at the end.
+    let mut status = 0;
     for filename in files {
-        let reader: Box<dyn io::Read> = if filename == "-" {
-            Box::new(io::stdin())
+        if let Err(err) = cat_file(filename, &mut formatter, out_stat.as_ref(), &mut out) {
+            eprintln!("Error reading file `{}`: {}", filename, err);
+            status = 1;
+        }
+    }
+    out.flush().unwrap_or_else(|err| write_error(err));
+    process::exit(status);
+}
+
+/// Writes a single FILE operand to `out`. Errors returned from here
+/// concern reading the input; failing to write is fatal and handled
+/// on the spot.
+fn cat_file<W: Write>(
+    filename: &str,
+    formatter: &mut Formatter,
+    out_stat: Option<&libc::stat>,
+    out: &mut W,
+) -> io::Result<()> {
+    let input = Input::open(filename)?;
+    if let Some(out_stat) = out_stat {
+        check_not_output(&input, out_stat)?;
+    }
+    if formatter.is_plain() {
+        dump_file(input, out)
+    } else {
+        formatter.format_file(input, out)
+    }
+}
+
+/// Refuses to read a regular file that is also standard output, as
+/// GNU cat does. Unless we're reading from past its end, copying such
+/// a file onto itself would never finish.
+fn check_not_output(input: &Input, out_stat: &libc::stat) -> io::Result<()> {
+    if out_stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+        return Ok(());
+    }
+    let fd = input.as_raw_fd();
+    let in_stat = fstat(fd)?;
+    if in_stat.st_dev != out_stat.st_dev || in_stat.st_ino != out_stat.st_ino {
+        return Ok(());
+    }
+    let position = unsafe { libc::lseek(fd, 0, libc::SEEK_CUR) };
+    if position >= 0 && position >= in_stat.st_size {
+        return Ok(());
+    }
+    Err(io::Error::other("input file is output file"))
+}
+
+/// Size of the buffer used to shuttle data from input to output.
+const BUF_SIZE: usize = 128 * 1024;
+
+/// A FILE operand, opened for reading.
+enum Input {
+    Stdin(io::Stdin),
+    File(fs::File),
+}
+
+impl Input {
+    fn open(filename: &str) -> io::Result<Input> {
+        if filename == "-" {
+            Ok(Input::Stdin(io::stdin()))
         } else {
-            Box::new(fs::File::open(filename).unwrap_or_else(|err| {
-                eprintln!("Error reading file `{}`: {}", filename, err);
-                process::exit(1);
-            }))
+            fs::File::open(filename).map(Input::File)
+        }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::Stdin(stdin) => stdin.read(buf),
+            Input::File(file) => file.read(buf),
+        }
+    }
+}
+
+impl AsRawFd for Input {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Input::Stdin(stdin) => stdin.as_raw_fd(),
+            Input::File(file) => file.as_raw_fd(),
+        }
+    }
+}
+
+/// Copies everything readable from `input` to standard output byte
+/// for byte. No decoding is done, so binary data and multi-byte
+/// characters split across reads come out exactly as they went in.
+///
+/// The kernel is asked to move the data itself first. Only if it
+/// declines do we fall back to reading into a buffer and writing that
+/// to `out`.
+fn dump_file<W: Write>(mut input: Input, out: &mut W) -> io::Result<()> {
+    // Whatever is still sitting in `out` has to land before the
+    // kernel starts writing to stdout behind its back.
+    out.flush().unwrap_or_else(|err| write_error(err));
+    match kernel_copy(input.as_raw_fd(), libc::STDOUT_FILENO) {
+        Ok(true) => return Ok(()),
+        Ok(false) => (),
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => write_error(err),
+        Err(err) => return Err(err),
+    }
+
+    let mut buffer = vec![0; BUF_SIZE];
+    loop {
+        let n = match input.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
         };
-        dump_file(reader, filename)
+        out.write_all(&buffer[..n])
+            .unwrap_or_else(|err| write_error(err));
+        // A short read usually means the input (a terminal or a pipe)
+        // has nothing more for us right now. Don't sit on what we
+        // have while waiting for more.
+        if n < buffer.len() {
+            out.flush().unwrap_or_else(|err| write_error(err));
+        }
     }
 }
 
-fn dump_file(mut reader: Box<dyn io::Read>, filename: &str) {
-    const BUF_SIZE: usize = 1024;
+/// Largest amount we ask the kernel to copy in a single call.
+#[cfg(target_os = "linux")]
+const KERNEL_CHUNK: usize = 1 << 30;
 
-    let mut buffer = [0; BUF_SIZE];
+/// Moves the rest of `in_fd` to `out_fd` without the data passing
+/// through userspace: `splice` when the output is a pipe,
+/// `copy_file_range` between regular files, and `sendfile` otherwise.
+///
+/// Returns `Ok(false)` if the kernel won't do this for the given pair
+/// of descriptors. Anything copied before it gave up is reflected in
+/// the file offsets, so the caller can carry on with plain reads and
+/// writes from where it left off.
+#[cfg(target_os = "linux")]
+fn kernel_copy(in_fd: RawFd, out_fd: RawFd) -> io::Result<bool> {
+    let in_stat = fstat(in_fd)?;
+    let out_stat = fstat(out_fd)?;
+    let is_type = |stat: &libc::stat, kind| stat.st_mode & libc::S_IFMT == kind;
+    let null = std::ptr::null_mut();
+
+    if is_type(&out_stat, libc::S_IFIFO) {
+        let done = copy_with(|| unsafe {
+            libc::splice(in_fd, null, out_fd, null, KERNEL_CHUNK, libc::SPLICE_F_MOVE)
+        })?;
+        if done {
+            return Ok(true);
+        }
+    }
+    if is_type(&in_stat, libc::S_IFREG) && is_type(&out_stat, libc::S_IFREG) {
+        let done = copy_with(|| unsafe {
+            libc::copy_file_range(in_fd, null, out_fd, null, KERNEL_CHUNK, 0)
+        })?;
+        if done {
+            return Ok(true);
+        }
+    }
+    copy_with(|| unsafe { libc::sendfile(out_fd, in_fd, null, KERNEL_CHUNK) })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn kernel_copy(_in_fd: RawFd, _out_fd: RawFd) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Repeats one of the copying syscalls until it reports end of input.
+///
+/// Returns `Ok(false)` when the syscall isn't supported for these
+/// descriptors. The same goes for hitting "end of input" before a
+/// single byte was copied: some special files (those in /proc, say)
+/// claim to be empty to these calls while having plenty to read.
+#[cfg(target_os = "linux")]
+fn copy_with<F: FnMut() -> isize>(mut copy: F) -> io::Result<bool> {
+    let mut copied = false;
     loop {
-        let n = reader.read(&mut buffer).unwrap_or_else(|err| {
-            eprintln!("Error reading file `{}`: {}", filename, err);
-            process::exit(1);
-        });
-        if n == 0 {
-            break;
-        }
-        let s = str::from_utf8(&buffer[0..n]).unwrap_or_else(|err| {
-            eprintln!("Error while reading file `{}`: {}", filename, err);
-            process::exit(1);
-        });
-        print!("{}", s);
+        match copy() {
+            0 => return Ok(copied),
+            n if n > 0 => copied = true,
+            _ => {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => (),
+                    Some(
+                        libc::EINVAL
+                        | libc::ENOSYS
+                        | libc::EXDEV
+                        | libc::EOPNOTSUPP
+                        | libc::EBADF
+                        | libc::ESPIPE
+                        | libc::EPERM,
+                    ) => return Ok(false),
+                    _ => return Err(err),
+                }
+            }
+        }
+    }
+}
+
+fn fstat(fd: RawFd) -> io::Result<libc::stat> {
+    let mut stat = MaybeUninit::uninit();
+    if unsafe { libc::fstat(fd, stat.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { stat.assume_init() })
+}
+
+/// The line-aware transformations requested on the command line,
+/// along with the state that has to carry over from one FILE to the
+/// next: the line count, whether the previous file ended partway
+/// through a line, and how many blank lines we've just seen.
+struct Formatter {
+    number: bool,
+    number_nonblank: bool,
+    squeeze_blank: bool,
+    show_ends: bool,
+    show_tabs: bool,
+    show_nonprinting: bool,
+
+    line_number: u64,
+    at_line_start: bool,
+    blank_lines: u64,
+}
+
+impl Formatter {
+    /// True when no transformation was asked for, so the input can be
+    /// copied through untouched.
+    fn is_plain(&self) -> bool {
+        !(self.number
+            || self.number_nonblank
+            || self.squeeze_blank
+            || self.show_ends
+            || self.show_tabs
+            || self.show_nonprinting)
+    }
+
+    fn format_file<W: Write>(&mut self, input: Input, out: &mut W) -> io::Result<()> {
+        let mut reader = io::BufReader::with_capacity(BUF_SIZE, input);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => return Ok(()),
+                Ok(_) => (),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+            self.format_line(&line, out)
+                .unwrap_or_else(|err| write_error(err));
+            // Same reasoning as in dump_file: keep interactive input
+            // flowing.
+            if reader.buffer().is_empty() {
+                out.flush().unwrap_or_else(|err| write_error(err));
+            }
+        }
+    }
+
+    /// Writes one line, which may lack its terminating newline if it
+    /// is the last in the file.
+    fn format_line<W: Write>(&mut self, line: &[u8], out: &mut W) -> io::Result<()> {
+        let (body, has_newline) = match line.split_last() {
+            Some((b'\n', body)) => (body, true),
+            _ => (line, false),
+        };
+
+        if self.at_line_start {
+            if body.is_empty() && has_newline {
+                self.blank_lines += 1;
+                if self.squeeze_blank && self.blank_lines > 1 {
+                    return Ok(());
+                }
+            } else {
+                self.blank_lines = 0;
+            }
+
+            if self.number || (self.number_nonblank && !body.is_empty()) {
+                self.line_number += 1;
+                write!(out, "{:>6}\t", self.line_number)?;
+            }
+        }
+
+        // With -E, a carriage return right before the newline is shown
+        // as ^M so that CRLF line endings stand out.
+        let (body, crlf) = match body.split_last() {
+            Some((b'\r', rest)) if has_newline && self.show_ends => (rest, true),
+            _ => (body, false),
+        };
+
+        if self.show_nonprinting || self.show_tabs {
+            for &c in body {
+                self.write_char(c, out)?;
+            }
+        } else {
+            out.write_all(body)?;
+        }
+
+        if crlf {
+            out.write_all(b"^M")?;
+        }
+        if has_newline {
+            if self.show_ends {
+                out.write_all(b"$")?;
+            }
+            out.write_all(b"\n")?;
+        }
+        self.at_line_start = has_newline;
+        Ok(())
+    }
+
+    /// Writes a single byte from the body of a line, using ^ and M-
+    /// notation where requested.
+    fn write_char<W: Write>(&self, c: u8, out: &mut W) -> io::Result<()> {
+        if c == b'\t' {
+            return if self.show_tabs {
+                out.write_all(b"^I")
+            } else {
+                out.write_all(&[c])
+            };
+        }
+        if !self.show_nonprinting {
+            return out.write_all(&[c]);
+        }
+
+        let c = if c >= 128 {
+            out.write_all(b"M-")?;
+            c - 128
+        } else {
+            c
+        };
+        match c {
+            0..=31 => out.write_all(&[b'^', c + 64]),
+            127 => out.write_all(b"^?"),
+            _ => out.write_all(&[c]),
+        }
     }
 }
@@ -1,14 +1,24 @@
 //! head - outputs the first part of files
 
 use clap::{App, Arg};
+use coreutils_rs::input::Input;
+use coreutils_rs::size::{parse_size, SizeError};
+use coreutils_rs::write_error;
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io;
-use std::str;
+use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
+use std::process;
 
 #[derive(Copy, Clone)]
 enum ToRead {
-    NumBytes(i64),
-    NumLines(i64),
+    NumBytes(u64),
+    NumLines(u64),
+    /// Everything except the last N bytes.
+    AllButLastBytes(u64),
+    /// Everything except the last N lines.
+    AllButLastLines(u64),
 }
 
 fn main() {
@@ -22,6 +32,7 @@ fn main() {
                 .long("bytes")
                 .takes_value(true)
                 .value_name("NUM")
+                .allow_hyphen_values(true)
                 .help("Print the first NUM bytes of each file; with the leading '-', print all but the last NUM bytes of each file")
         )
         .arg(
@@ -30,9 +41,27 @@ fn main() {
                 .long("lines")
                 .takes_value(true)
                 .value_name("NUM")
+                .allow_hyphen_values(true)
                 .default_value("10")
                 .help("Print the first NUM lines instead of the first 10; with the leading '-', print all but the last NUM lines of each file")
         )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .alias("silent")
+                .takes_value(false)
+                .multiple_occurrences(true)
+                .help("Never print headers giving file names")
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .takes_value(false)
+                .multiple_occurrences(true)
+                .help("Always print headers giving file names")
+        )
         .arg(
             Arg::new("zero")
                 .short('z')
@@ -52,63 +81,268 @@ fn main() {
 
     let to_read = if matches.is_present("bytes") {
         let val = matches.value_of("bytes").unwrap();
-        if val.chars().next().unwrap() == '-' {
-            panic!("Leading `-' is unsupported")
+        match val.strip_prefix('-') {
+            Some(num) => ToRead::AllButLastBytes(parse_count(num, val, "bytes")),
+            None => ToRead::NumBytes(parse_count(val, val, "bytes")),
         }
-        let c = val.parse::<i64>().unwrap();
-        ToRead::NumBytes(c)
     } else {
         let val = matches.value_of("lines").unwrap();
-        if val.chars().next().unwrap() == '-' {
-            panic!("Leading `-' is unsupported")
+        match val.strip_prefix('-') {
+            Some(num) => ToRead::AllButLastLines(parse_count(num, val, "lines")),
+            None => ToRead::NumLines(parse_count(val, val, "lines")),
         }
-        let n = val.parse::<i64>().unwrap();
-        ToRead::NumLines(n)
     };
 
+    // Headers are printed when there's more than one file, unless
+    // asked otherwise. Whichever of -q and -v comes last wins.
+    let last_index = |name| matches.indices_of(name).and_then(|i| i.max());
+    let print_headers = match (last_index("quiet"), last_index("verbose")) {
+        (Some(q), Some(v)) => v > q,
+        (Some(_), None) => false,
+        (None, Some(_)) => true,
+        (None, None) => files.len() > 1,
+    };
+
+    let delim = if matches.is_present("zero") { 0 } else { b'\n' };
+
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::with_capacity(BUF_SIZE, stdout.lock());
+
+    let mut status = 0;
+    let mut first_header = true;
     for filename in files {
-        // Open the file
-        let reader: Box<dyn io::BufRead> = match filename.as_ref() {
-            "-" => Box::new(io::BufReader::new(io::stdin())),
-            filename => Box::new(io::BufReader::new(File::open(filename).unwrap())),
+        let input = match Input::open(filename) {
+            Ok(input) => input,
+            Err(err) => {
+                eprintln!("Error reading file `{}`: {}", filename, err);
+                status = 1;
+                continue;
+            }
         };
 
-        head(reader, to_read, '\n' as u8).expect("Read lines");
+        if print_headers {
+            let name = if filename == "-" {
+                "standard input"
+            } else {
+                filename
+            };
+            let res = writeln!(
+                out,
+                "{}==> {} <==",
+                if first_header { "" } else { "\n" },
+                name
+            );
+            res.unwrap_or_else(|err| write_error(err));
+            first_header = false;
+        }
+
+        if let Err(err) = head(&input, to_read, delim, &mut out) {
+            if err.kind() == io::ErrorKind::BrokenPipe {
+                write_error(err);
+            }
+            eprintln!("Error reading file `{}`: {}", filename, err);
+            status = 1;
+        }
+        out.flush().unwrap_or_else(|err| write_error(err));
     }
+    process::exit(status);
 }
 
-fn head(mut reader: Box<dyn io::BufRead>, to_read: ToRead, delim: u8) -> std::io::Result<()> {
-    match to_read {
-        ToRead::NumBytes(nbytes) => {
-            let bytes = nbytes as usize;
-
-            let mut written: usize = 0;
-            while written < bytes {
-                // Read until the delimiter
-                let mut line = vec![];
-                reader.read_until(delim, &mut line)?;
-                // Then write the number of bytes in the line or the
-                // number remaining in our alloted balance. Whichever
-                // is fewer.
-                if line.len() < bytes - written {
-                    written += line.len();
-                    print!("{}", str::from_utf8(&line).unwrap());
-                } else {
-                    let short = line.get(0..(bytes - written)).unwrap();
-                    written += short.len();
-                    print!("{}", str::from_utf8(&short).unwrap());
-                }
+/// Parses `num`, the NUM given to -c or -n less any leading '-',
+/// exiting with a diagnostic quoting the whole of `val` if it isn't
+/// a valid count.
+fn parse_count(num: &str, val: &str, what: &str) -> u64 {
+    parse_size(num, false).unwrap_or_else(|err| {
+        match err {
+            SizeError::Invalid | SizeError::InvalidSuffix => {
+                eprintln!("Invalid number of {}: '{}'", what, val)
             }
+            SizeError::Overflow => eprintln!(
+                "Invalid number of {}: '{}': Value too large for defined data type",
+                what, val
+            ),
+        }
+        process::exit(1);
+    })
+}
+
+/// Size of the buffer used when copying from input to output.
+const BUF_SIZE: usize = 128 * 1024;
 
+fn head<W: Write>(mut file: &File, to_read: ToRead, delim: u8, out: &mut W) -> io::Result<()> {
+    let mut reader = io::BufReader::with_capacity(BUF_SIZE, file);
+    match to_read {
+        ToRead::NumBytes(n) => copy_bytes(file, n, out),
+        ToRead::NumLines(n) => {
+            copy_lines(&mut reader, n, delim, out)?;
+            // Leave the file offset just past the last line we
+            // printed, so that whoever reads this file next (the
+            // next `-` operand, or the rest of a shell script sharing
+            // our stdin) picks up from there. Not every input can
+            // seek; that's fine.
+            let unread = reader.buffer().len() as i64;
+            if unread > 0 {
+                let _ = file.seek(io::SeekFrom::Current(-unread));
+            }
             Ok(())
         }
-        ToRead::NumLines(nlines) => {
-            for _i in 0..nlines {
-                let mut line = vec![];
-                reader.read_until(delim, &mut line)?;
-                print!("{}", str::from_utf8(&line).unwrap());
+        ToRead::AllButLastBytes(n) => match seekable_range(file)? {
+            Some((pos, size)) => copy_bytes(file, size.saturating_sub(pos).saturating_sub(n), out),
+            None => elide_tail_bytes(file, n, out),
+        },
+        ToRead::AllButLastLines(n) => match seekable_range(file)? {
+            Some((pos, size)) => {
+                let end = find_tail_lines(file, pos, size, n, delim)?;
+                file.seek(io::SeekFrom::Start(pos))?;
+                copy_bytes(file, end - pos, out)
             }
-            Ok(())
+            None => elide_tail_lines(&mut reader, n, delim, out),
+        },
+    }
+}
+
+/// Returns the current offset and the size of `file` if we can rely
+/// on seeking around in it. That means a regular file; small ones are
+/// left out since files in /proc and the like report made-up sizes,
+/// and reading a file that small straight through costs nothing.
+fn seekable_range(mut file: &File) -> io::Result<Option<(u64, u64)>> {
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() <= metadata.blksize() {
+        return Ok(None);
+    }
+    match file.stream_position() {
+        Ok(pos) => Ok(Some((pos, metadata.len()))),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Copies exactly `count` bytes from `file` to `out`, or fewer if the
+/// file ends first.
+fn copy_bytes<W: Write>(file: &File, count: u64, out: &mut W) -> io::Result<()> {
+    let mut input = file.take(count);
+    let mut buffer = vec![0; BUF_SIZE];
+    loop {
+        match input.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => out.write_all(&buffer[..n])?,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Copies the first `n` lines of `reader` to `out`, or all of it if
+/// it has fewer lines than that.
+fn copy_lines<R: BufRead, W: Write>(
+    reader: &mut R,
+    mut n: u64,
+    delim: u8,
+    out: &mut W,
+) -> io::Result<()> {
+    while n > 0 {
+        let buffer = match reader.fill_buf() {
+            Ok([]) => return Ok(()),
+            Ok(buffer) => buffer,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        let mut len = buffer.len();
+        for (i, _) in buffer.iter().enumerate().filter(|&(_, &c)| c == delim) {
+            n -= 1;
+            if n == 0 {
+                len = i + 1;
+                break;
+            }
+        }
+        out.write_all(&buffer[..len])?;
+        reader.consume(len);
+    }
+    Ok(())
+}
+
+/// Scans `file` backwards from `size` and returns the offset where
+/// its last `n` lines begin, or `start` if it doesn't have that many
+/// past `start`. A final line with no delimiter counts as a line.
+fn find_tail_lines(mut file: &File, start: u64, size: u64, n: u64, delim: u8) -> io::Result<u64> {
+    if n == 0 || size <= start {
+        return Ok(size.max(start));
+    }
+
+    let mut buffer = vec![0; BUF_SIZE];
+    let mut end = size;
+    // The delimiter ending the very last line doesn't separate it
+    // from anything, so skip over it.
+    let mut remaining = n + 1;
+    let mut first = true;
+    while end > start {
+        let len = (end - start).min(BUF_SIZE as u64) as usize;
+        let begin = end - len as u64;
+        file.seek(io::SeekFrom::Start(begin))?;
+        file.read_exact(&mut buffer[..len])?;
+        if first && buffer[len - 1] != delim {
+            remaining -= 1;
+        }
+        first = false;
+        for i in (0..len).rev() {
+            if buffer[i] == delim {
+                remaining -= 1;
+                if remaining == 0 {
+                    return Ok(begin + i as u64 + 1);
+                }
+            }
+        }
+        end = begin;
+    }
+    Ok(start)
+}
+
+/// Copies all but the last `n` bytes of `file` to `out` without
+/// knowing in advance how long it is: the most recent `n` bytes are
+/// held back in a ring buffer, and whatever falls out of it is
+/// written.
+fn elide_tail_bytes<W: Write>(mut file: &File, n: u64, out: &mut W) -> io::Result<()> {
+    let n = n as usize;
+    let mut ring = VecDeque::new();
+    let mut buffer = vec![0; BUF_SIZE];
+    loop {
+        let len = match file.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        ring.extend(&buffer[..len]);
+        if ring.len() > n {
+            let excess = ring.len() - n;
+            let (front, back) = ring.as_slices();
+            if excess <= front.len() {
+                out.write_all(&front[..excess])?;
+            } else {
+                out.write_all(front)?;
+                out.write_all(&back[..excess - front.len()])?;
+            }
+            ring.drain(..excess);
+        }
+    }
+}
+
+/// Copies all but the last `n` lines of `reader` to `out`, holding
+/// the most recent `n` lines back until we know they aren't the last.
+fn elide_tail_lines<R: BufRead, W: Write>(
+    reader: &mut R,
+    n: u64,
+    delim: u8,
+    out: &mut W,
+) -> io::Result<()> {
+    let mut ring = VecDeque::new();
+    loop {
+        let mut line = Vec::new();
+        if reader.read_until(delim, &mut line)? == 0 {
+            return Ok(());
+        }
+        ring.push_back(line);
+        if ring.len() as u64 > n {
+            out.write_all(&ring.pop_front().unwrap())?;
         }
     }
 }
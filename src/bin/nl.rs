@@ -11,7 +11,7 @@ fn main() {
     let matches = App::new("rust-nl")
         .version("0.1.0")
         .author("Jos V. <jos@josaphat.co>")
-        .about("Rust clone of the nl utility.\nNOTE: It is missing a few things, including the `pBRE` STYLE option. See documentation for more details.")
+        .about("Rust clone of the nl utility.\nNOTE: It is missing a few things. See documentation for more details.")
         .arg(
             Arg::new("body_style")
                 .short('b')
@@ -27,7 +27,8 @@ fn main() {
                 .long("section-delimiter")
                 .takes_value(true)
                 .value_name("CC")
-                .help("(CURRENTLY IGNORED) use CC for logical page delimiters"),
+                .help("use CC for logical page delimiters")
+                .default_value("\\:"),
         )
         .arg(
             Arg::new("footer_style")
@@ -53,9 +54,19 @@ fn main() {
                 .long("line-increment")
                 .takes_value(true)
                 .value_name("NUMBER")
+                .allow_hyphen_values(true)
                 .help("line number increment at each line")
                 .default_value("1"),
         )
+        .arg(
+            Arg::new("join_blank_lines")
+                .short('l')
+                .long("join-blank-lines")
+                .takes_value(true)
+                .value_name("NUMBER")
+                .help("group of NUMBER empty lines counted as one")
+                .default_value("1"),
+        )
         .arg(
             Arg::new("number_format")
                 .short('n')
@@ -84,6 +95,7 @@ fn main() {
                 .short('v')
                 .long("starting-line-number")
                 .value_name("NUMBER")
+                .allow_hyphen_values(true)
                 .help("first line number for each section")
                 .default_value("1"),
         )
@@ -98,7 +110,9 @@ fn main() {
         .arg(
             Arg::new("file")
                 .help("A pathname of a text file to be line-numbered.")
-                .required(false),
+                .required(false)
+                .default_value("-")
+                .multiple_occurrences(true),
         )
         .after_help("Default options are: -bt -d'\\:' -fn -hn -i1 -l1 -n'rn' -s<TAB> -v1 -w6\n\n\
                      CC are two delimiter characters used to construct logical page delimiters; a missing second character implies ':'.\n\n\
@@ -106,31 +120,46 @@ fn main() {
                      \ta\t\tnumber all lines\n\
                      \tt\t\tnumber only nonempty lines\n\
                      \tn\t\tnumber no lines\n\
-                     \tpBRE\t\tnumber only lines that contain a match for the basic regular expression, BRE (currently ignored)\n\
+                     \tpBRE\t\tnumber only lines that contain a match for the basic regular expression, BRE\n\
                      \nFORMAT is one of:\n\n\
                      \tln\t\tleft justified, no leading zeros\n\
                      \trn\t\tright justified, no leading zeros\n\
                      \trz\t\tright justified, leading zeros\n")
         .get_matches();
 
+    // Regular expressions are matched according to the locale, as in
+    // GNU nl: a character at a time in a UTF-8 one.
+    unsafe { libc::setlocale(libc::LC_ALL, c"".as_ptr()) };
+
     let no_renumber = matches.is_present("no_renumber");
 
     let body_style = matches.value_of("body_style").unwrap_or_else(|| {
         eprintln!("Could not parse body style parameter");
         process::exit(1);
     });
+    let body_style = parse_style(body_style, "body");
 
-    // Ignoring section_delim...
+    // Logical pages are delimited by lines holding CC three times
+    // (header), twice (body) or once (footer). Following POSIX, a
+    // lone delimiter character is completed with ':'. Any other
+    // length is taken as is, as in GNU nl; an empty CC means there are
+    // no delimiters.
+    let mut section_delim = matches.value_of("section_delim").unwrap().to_string();
+    if section_delim.len() == 1 {
+        section_delim.push(':');
+    }
 
     let header_style = matches.value_of("header_style").unwrap_or_else(|| {
         eprintln!("Could not parse header style parameter");
         process::exit(1);
     });
+    let header_style = parse_style(header_style, "header");
 
     let footer_style = matches.value_of("footer_style").unwrap_or_else(|| {
         eprintln!("Could not parse footer style parameter");
         process::exit(1);
     });
+    let footer_style = parse_style(footer_style, "footer");
 
     let number_format = matches.value_of("number_format").unwrap_or_else(|| {
         eprintln!("Could not parse -n, --number-format=FORMAT");
@@ -152,7 +181,7 @@ fn main() {
         process::exit(1);
     });
 
-    let starting_line_number = starting_line_str.parse::<u32>().unwrap_or_else(|err| {
+    let starting_line_number = starting_line_str.parse::<i64>().unwrap_or_else(|err| {
         eprintln!(
             "Invalid starting line number: '{}'\nError: {}",
             starting_line_str, err
@@ -161,11 +190,26 @@ fn main() {
         process::exit(1);
     });
 
-    let line_increment = matches
-        .value_of("line_increment")
+    let line_increment_str = matches.value_of("line_increment").unwrap();
+    let line_increment = line_increment_str.parse::<i64>().unwrap_or_else(|err| {
+        eprintln!(
+            "Invalid line number increment: '{}'\nError: {}",
+            line_increment_str, err
+        );
+        eprintln!("usage: -i, --line-increment=NUMBER");
+        process::exit(1);
+    });
+
+    let join_blank_lines = matches
+        .value_of("join_blank_lines")
         .unwrap()
-        .parse::<u32>()
-        .expect("Invalid value for -i, --line-increment=NUMBER");
+        .parse::<u64>()
+        .ok()
+        .filter(|&n| n > 0)
+        .unwrap_or_else(|| {
+            eprintln!("Invalid value for -l, --join-blank-lines=NUMBER");
+            process::exit(1);
+        });
 
     let number_width = matches
         .value_of("number_width")
@@ -173,41 +217,164 @@ fn main() {
         .parse::<usize>()
         .expect("Invalid value for -w, --number-width=NUMBER");
 
-    let filename = matches.value_of("file").unwrap_or("-");
-    let reader: Box<dyn io::BufRead> = match filename {
-        "-" => Box::new(io::BufReader::new(io::stdin())),
-        filename => Box::new(io::BufReader::new(fs::File::open(filename).unwrap())),
-    };
-
-    nl(
+    let options = Options {
         no_renumber,
+        header_delim: section_delim.repeat(3),
+        body_delim: section_delim.repeat(2),
+        footer_delim: section_delim,
         header_style,
         body_style,
         footer_style,
+        join_blank_lines,
         number_format,
         number_separator,
         starting_line_number,
         line_increment,
         number_width,
-        reader,
-    );
+    };
+
+    // All the FILEs are numbered as one continuous stream.
+    let mut state = State {
+        line_count: Some(starting_line_number),
+        section: "BODY",
+        blank_lines: 0,
+        unterminated: false,
+    };
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let mut status = 0;
+    for filename in matches.values_of("file").unwrap() {
+        let reader: Box<dyn io::BufRead> = match filename {
+            "-" => Box::new(io::BufReader::new(io::stdin())),
+            filename => match fs::File::open(filename) {
+                Ok(file) => Box::new(io::BufReader::new(file)),
+                Err(err) => {
+                    eprintln!("Error reading file `{}`: {}", filename, err);
+                    status = 1;
+                    continue;
+                }
+            },
+        };
+        if let Err(err) = nl(&options, &mut state, reader, &mut out) {
+            if err.kind() == io::ErrorKind::BrokenPipe {
+                process::exit(1);
+            }
+            eprintln!("Error reading file `{}`: {}", filename, err);
+            status = 1;
+        }
+    }
+    if let Err(err) = out.flush() {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("Error writing to standard output: {}", err);
+        }
+        status = 1;
+    }
+    process::exit(status);
+}
+
+/// How the lines of a section are chosen for numbering.
+enum Style {
+    /// `a`: number all lines.
+    All,
+    /// `t`: number only nonempty lines.
+    NonEmpty,
+    /// `n`: number no lines.
+    None,
+    /// `pBRE`: number only lines that contain a match for BRE.
+    Regex(bre::Regex),
+}
+
+impl Style {
+    fn numbers(&self, line: &[u8]) -> bool {
+        match self {
+            Style::All => true,
+            Style::NonEmpty => !line.is_empty(),
+            Style::None => false,
+            Style::Regex(re) => re.is_match(line),
+        }
+    }
 }
 
-fn nl(
+/// Parses the STYLE given for the header, body or footer (named by
+/// `section`), exiting with a diagnostic if it isn't valid.
+fn parse_style(style: &str, section: &str) -> Style {
+    match style {
+        "a" => Style::All,
+        "t" => Style::NonEmpty,
+        "n" => Style::None,
+        _ => match style.strip_prefix('p') {
+            Some(pattern) => {
+                Style::Regex(bre::Regex::new(pattern.as_bytes()).unwrap_or_else(|err| {
+                    eprintln!("Invalid regular expression '{}': {}", pattern, err);
+                    process::exit(1);
+                }))
+            }
+            None => {
+                eprintln!("Invalid {} numbering style: '{}'", section, style);
+                eprintln!("Valid values are 'a', 't', 'n' and 'pBRE'.");
+                process::exit(1);
+            }
+        },
+    }
+}
+
+/// Settings taken from the command line.
+struct Options<'a> {
     no_renumber: bool,
-    header_style: &str,
-    body_style: &str,
-    footer_style: &str,
-    number_format: &str,
-    number_separator: &str,
-    starting_line_number: u32,
-    line_increment: u32,
+    header_delim: String,
+    body_delim: String,
+    footer_delim: String,
+    header_style: Style,
+    body_style: Style,
+    footer_style: Style,
+    join_blank_lines: u64,
+    number_format: &'a str,
+    number_separator: &'a str,
+    starting_line_number: i64,
+    line_increment: i64,
     number_width: usize,
-    reader: Box<dyn io::BufRead>,
-) {
-    let mut line_count = starting_line_number;
-    let mut section = "BODY";
-    let map: collections::HashMap<&str, &str> = [
+}
+
+/// Where we are in the input, carried over from one FILE to the next.
+struct State {
+    /// The next line number, or `None` if it is out of range.
+    line_count: Option<i64>,
+    section: &'static str,
+    /// Empty lines seen since the last one numbered (for -l).
+    blank_lines: u64,
+    /// Whether the last FILE ended without a newline, which is owed
+    /// before anything more is written. Only the end of the whole
+    /// stream is left unterminated.
+    unterminated: bool,
+}
+
+/// Numbers the lines of `reader` onto `out`. Lines are handled as
+/// bytes, so the output is the input unchanged (invalid UTF-8, CRLF
+/// line endings, a missing final newline and all) apart from the
+/// numbers added in front.
+fn nl<W: Write>(
+    options: &Options,
+    state: &mut State,
+    mut reader: Box<dyn io::BufRead>,
+    out: &mut W,
+) -> io::Result<()> {
+    let Options {
+        no_renumber,
+        ref header_delim,
+        ref body_delim,
+        ref footer_delim,
+        ref header_style,
+        ref body_style,
+        ref footer_style,
+        join_blank_lines,
+        number_format,
+        number_separator,
+        starting_line_number,
+        line_increment,
+        number_width,
+    } = *options;
+
+    let map: collections::HashMap<&str, &Style> = [
         ("HEADER", header_style),
         ("BODY", body_style),
         ("FOOTER", footer_style),
@@ -215,82 +382,178 @@ fn nl(
     .iter()
     .cloned()
     .collect();
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        if reader.read_until(b'\n', &mut buffer)? == 0 {
+            return Ok(());
+        }
+        let (line, newline): (&[u8], &[u8]) = match buffer.split_last() {
+            Some((b'\n', line)) => (line, b"\n"),
+            _ => (&buffer, b""),
+        };
+        if state.unterminated {
+            out.write_all(b"\n")?;
+        }
+        state.unterminated = newline.is_empty();
+        let style = map[state.section];
 
-    for line in reader.lines() {
-        let line = line.unwrap_or_default();
-        let style = map[section];
-
-        if line == "\\:\\:\\:" {
+        let delimiter = if footer_delim.is_empty() {
+            None
+        } else if line == header_delim.as_bytes() {
+            Some("HEADER")
+        } else if line == body_delim.as_bytes() {
+            Some("BODY")
+        } else if line == footer_delim.as_bytes() {
+            Some("FOOTER")
+        } else {
+            None
+        };
+        if let Some(next_section) = delimiter {
             // The delimiter line is considered empty.
-            println!("{}", "");
+            out.write_all(newline)?;
             // Reset the line count
             if !no_renumber {
-                line_count = starting_line_number;
+                state.line_count = Some(starting_line_number);
             }
-            // This is the beginning of a header
-            section = "HEADER";
+            state.section = next_section;
             continue;
         }
-        if line == "\\:\\:" {
-            println!("{}", "");
-            // Reset the line count
-            if !no_renumber {
-                line_count = starting_line_number;
+
+        let mut number = style.numbers(line);
+        if let Style::All = style {
+            // With -l, a run of empty lines only gets a number once
+            // it is join_blank_lines long.
+            if join_blank_lines > 1 && line.is_empty() {
+                state.blank_lines += 1;
+                number = state.blank_lines == join_blank_lines;
             }
-            section = "BODY";
-            continue;
-        }
-        if line == "\\:" {
-            println!("{}", "");
-            // Reset the line count
-            if !no_renumber {
-                line_count = starting_line_number;
+            if number {
+                state.blank_lines = 0;
             }
-            section = "FOOTER";
-            continue;
         }
 
-        if style == "t" || style == "a" {
-            if line == "" && style == "t" {
-                // Do not number blank lines
-                println!("{}", line);
-            } else {
-                // We can't dynamically set alignment like we can
-                // width (AFAICT).  The only difference between these
-                // three arms is the alignment field (either ">",
-                // "0>", or "<").
-                match number_format {
-                    "rn" => println!(
-                        "{:>width$}{}{}",
-                        line_count,
-                        number_separator,
-                        line,
-                        width = number_width
-                    ),
-                    "rz" => println!(
-                        "{:0>width$}{}{}",
-                        line_count,
-                        number_separator,
-                        line,
-                        width = number_width
-                    ),
-                    "ln" => println!(
-                        "{:<width$}{}{}",
-                        line_count,
-                        number_separator,
-                        line,
-                        width = number_width
-                    ),
-                    _ => unreachable!(),
+        if number {
+            // As in GNU nl, running past the largest line number is
+            // only an error once we actually need that number.
+            let line_count = match state.line_count {
+                Some(line_count) => line_count,
+                None => {
+                    out.flush()?;
+                    eprintln!("line number overflow");
+                    process::exit(1);
                 }
-                line_count += line_increment;
+            };
+            // We can't dynamically set alignment like we can width
+            // (AFAICT).  The only difference between these three arms
+            // is the alignment field (either ">", "0", or "<"). The
+            // "0" flag, unlike "0>", pads after any minus sign.
+            match number_format {
+                "rn" => write!(
+                    out,
+                    "{:>width$}{}",
+                    line_count,
+                    number_separator,
+                    width = number_width
+                )?,
+                "rz" => write!(
+                    out,
+                    "{:0width$}{}",
+                    line_count,
+                    number_separator,
+                    width = number_width
+                )?,
+                "ln" => write!(
+                    out,
+                    "{:<width$}{}",
+                    line_count,
+                    number_separator,
+                    width = number_width
+                )?,
+                _ => unreachable!(),
             }
-        } else if style == "n" {
+            state.line_count = line_count.checked_add(line_increment);
+        } else {
             // No numbering. Only printing.  Account for the width of
             // the separator. We want the outputs to line up with
-            // non-numbered sections.
+            // numbered lines.
             let indent = " ".repeat(number_width + number_separator.len());
-            println!("{}{}", indent, line);
+            out.write_all(indent.as_bytes())?;
+        }
+        out.write_all(line)?;
+        out.write_all(newline)?;
+    }
+}
+
+/// A POSIX basic regular expression, compiled by the C library, so that
+/// a `pBRE` style means just what it does to GNU nl: glibc's syntax,
+/// GNU extensions and all, matched as the current locale has it.
+mod bre {
+    use std::ffi::{CStr, CString};
+    use std::fmt;
+    use std::mem::MaybeUninit;
+    use std::os::raw::c_char;
+
+    pub struct Regex(libc::regex_t);
+
+    #[derive(Debug)]
+    pub struct Error(String);
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(&self.0)
+        }
+    }
+
+    impl Regex {
+        pub fn new(pattern: &[u8]) -> Result<Regex, Error> {
+            let pattern = CString::new(pattern)
+                .map_err(|_| Error("Pattern contains a null byte".to_string()))?;
+            let mut regex = MaybeUninit::<libc::regex_t>::uninit();
+            // We only ask whether a line matches, never where.
+            let code =
+                unsafe { libc::regcomp(regex.as_mut_ptr(), pattern.as_ptr(), libc::REG_NOSUB) };
+            if code != 0 {
+                let mut message = [0u8; 256];
+                unsafe {
+                    libc::regerror(
+                        code,
+                        regex.as_ptr(),
+                        message.as_mut_ptr() as *mut c_char,
+                        message.len(),
+                    )
+                };
+                let message = CStr::from_bytes_until_nul(&message).unwrap_or_default();
+                return Err(Error(message.to_string_lossy().into_owned()));
+            }
+            Ok(Regex(unsafe { regex.assume_init() }))
+        }
+
+        /// Returns true if `text` contains a match anywhere.
+        pub fn is_match(&self, text: &[u8]) -> bool {
+            // With REG_STARTEND the text runs to `rm_eo` rather than to
+            // a null byte, so a line holding one is matched in full.
+            let mut range = libc::regmatch_t {
+                rm_so: 0,
+                rm_eo: text.len() as libc::regoff_t,
+            };
+            let text = if text.is_empty() { &b"\0"[..] } else { text };
+            let code = unsafe {
+                libc::regexec(
+                    &self.0,
+                    text.as_ptr() as *const c_char,
+                    1,
+                    &mut range,
+                    libc::REG_STARTEND,
+                )
+            };
+            code == 0
+        }
+    }
+
+    impl Drop for Regex {
+        fn drop(&mut self) {
+            unsafe { libc::regfree(&mut self.0) };
         }
     }
 }
@@ -1,13 +1,20 @@
 //! od - dump files in various formats
 
 use clap::{App, Arg};
+use coreutils_rs::input::Input as Reader;
+use coreutils_rs::size::{parse_size, SizeError};
+use coreutils_rs::write_error;
+use std::env;
+use std::ffi::OsString;
 use std::fs::File;
 use std::io;
 use std::io::ErrorKind;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::io::Write;
+use std::os::unix::fs::MetadataExt;
 use std::process;
-use std::vec;
 
 enum AddressRadix {
     Octal,
@@ -16,51 +23,351 @@ enum AddressRadix {
     None,
 }
 
+#[derive(Clone, Copy, PartialEq)]
 enum Endian {
     Little,
-    // Big,
-}
-
-// enum FormatType {
-//     NamedCharacter,
-//     PrintableCharacter,
-//     SignedDecimal,
-//     Octal,
-//     UnsignedDecimal,
-//     Hexadecimal,
-// }
-
-// enum CharacterWidth {
-//     Char,
-//     Short,
-//     Int,
-//     Long,
-//     Float,
-//     Double,
-//     LongDouble,
-// }
+    Big,
+}
+
+impl Endian {
+    /// The byte order of the machine we're running on.
+    fn native() -> Endian {
+        if cfg!(target_endian = "big") {
+            Endian::Big
+        } else {
+            Endian::Little
+        }
+    }
+}
+
+/// The type letters of -t.
+#[derive(Clone, Copy, PartialEq)]
+enum FormatType {
+    /// `a`: named character, ignoring the high-order bit.
+    NamedCharacter,
+    /// `c`: printable character or backslash escape.
+    PrintableCharacter,
+    /// `d`: signed decimal.
+    SignedDecimal,
+    /// `f`: floating point.
+    FloatingPoint,
+    /// `o`: octal.
+    Octal,
+    /// `u`: unsigned decimal.
+    UnsignedDecimal,
+    /// `x`: hexadecimal.
+    Hexadecimal,
+}
+
+/// The C types whose names may follow a type letter in -t in place
+/// of a byte count.
+enum CharacterWidth {
+    Char,
+    Short,
+    Int,
+    Long,
+    Float,
+    Double,
+    LongDouble,
+}
 
+impl CharacterWidth {
+    fn from_suffix(ftype: FormatType, suffix: u8) -> Option<CharacterWidth> {
+        let float = ftype == FormatType::FloatingPoint;
+        match (float, suffix) {
+            (false, b'C') => Some(CharacterWidth::Char),
+            (false, b'S') => Some(CharacterWidth::Short),
+            (false, b'I') => Some(CharacterWidth::Int),
+            (false, b'L') => Some(CharacterWidth::Long),
+            (true, b'F') => Some(CharacterWidth::Float),
+            (true, b'D') => Some(CharacterWidth::Double),
+            (true, b'L') => Some(CharacterWidth::LongDouble),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        match self {
+            CharacterWidth::Char => 1,
+            CharacterWidth::Short => 2,
+            CharacterWidth::Int => 4,
+            CharacterWidth::Long => std::mem::size_of::<libc::c_long>(),
+            CharacterWidth::Float => 4,
+            CharacterWidth::Double => 8,
+            CharacterWidth::LongDouble => long_double::SIZE,
+        }
+    }
+}
+
+/// One output format: a line of these is printed for every block of
+/// input.
 struct Format {
-    // ftype: FormatType,
+    ftype: FormatType,
+    /// Size in bytes of each value.
     character_width: usize,
-    // is_display: bool,
+    /// The `z` suffix: follow the line with the block's printable
+    /// characters.
+    show_chars: bool,
 }
 
-fn parse_i16(a: u8, b: u8, endian: Endian) -> i16 {
-    let x: i16 = a.into();
-    let y: i16 = b.into();
+/// Names used by `-t a` for the control characters and space.
+const CHARACTER_NAMES: [&str; 33] = [
+    "nul", "soh", "stx", "etx", "eot", "enq", "ack", "bel", "bs", "ht", "nl", "vt", "ff", "cr",
+    "so", "si", "dle", "dc1", "dc2", "dc3", "dc4", "nak", "syn", "etb", "can", "em", "sub", "esc",
+    "fs", "gs", "rs", "us", "sp",
+];
+
+impl Format {
+    /// Width of the widest value this format can produce.
+    fn field_width(&self) -> usize {
+        let bits = 8 * self.character_width as u32;
+        match self.ftype {
+            FormatType::NamedCharacter | FormatType::PrintableCharacter => 3,
+            FormatType::SignedDecimal => (-(1i128 << (bits - 1))).to_string().len(),
+            FormatType::UnsignedDecimal => ((1u128 << bits) - 1).to_string().len(),
+            FormatType::Octal => (bits as usize).div_ceil(3),
+            FormatType::Hexadecimal => 2 * self.character_width,
+            FormatType::FloatingPoint => match self.character_width {
+                4 => 15,
+                8 => 24,
+                _ => long_double::FIELD_WIDTH,
+            },
+        }
+    }
+
+    /// Formats the value in `bytes`, which holds `character_width`
+    /// bytes.
+    fn format_value(&self, bytes: &[u8], endian: Endian) -> String {
+        match self.ftype {
+            FormatType::NamedCharacter => {
+                let c = bytes[0] & 0x7f;
+                match c {
+                    0..=32 => CHARACTER_NAMES[c as usize].to_string(),
+                    127 => "del".to_string(),
+                    _ => (c as char).to_string(),
+                }
+            }
+            FormatType::PrintableCharacter => match bytes[0] {
+                0 => "\\0".to_string(),
+                7 => "\\a".to_string(),
+                8 => "\\b".to_string(),
+                9 => "\\t".to_string(),
+                10 => "\\n".to_string(),
+                11 => "\\v".to_string(),
+                12 => "\\f".to_string(),
+                13 => "\\r".to_string(),
+                c @ 0x20..=0x7e => (c as char).to_string(),
+                c => format!("{:03o}", c),
+            },
+            FormatType::SignedDecimal => {
+                let shift = 64 - 8 * self.character_width;
+                (((parse_uint(bytes, endian) << shift) as i64) >> shift).to_string()
+            }
+            FormatType::UnsignedDecimal => parse_uint(bytes, endian).to_string(),
+            FormatType::Octal => format!(
+                "{:0width$o}",
+                parse_uint(bytes, endian),
+                width = self.field_width()
+            ),
+            FormatType::Hexadecimal => format!(
+                "{:0width$x}",
+                parse_uint(bytes, endian),
+                width = self.field_width()
+            ),
+            FormatType::FloatingPoint => match self.character_width {
+                4 => {
+                    let x = f32::from_bits(parse_uint(bytes, endian) as u32);
+                    let min_digits = if x.abs() < f32::MIN_POSITIVE { 1 } else { 6 };
+                    format_float(x.into(), min_digits, 9, |s| s.parse() == Ok(x))
+                }
+                8 => {
+                    let x = f64::from_bits(parse_uint(bytes, endian));
+                    let min_digits = if x.abs() < f64::MIN_POSITIVE { 1 } else { 15 };
+                    format_float(x, min_digits, 17, |s| s.parse() == Ok(x))
+                }
+                _ => long_double::format(bytes, endian),
+            },
+        }
+    }
+}
 
+/// Reads an unsigned integer from `bytes`, at most 8 of them.
+fn parse_uint(bytes: &[u8], endian: Endian) -> u64 {
     match endian {
-        Endian::Little => x << 0 | y << 8,
-        // Endian::Big => y << 0 | x << 8,
+        Endian::Little => bytes
+            .iter()
+            .rev()
+            .fold(0, |acc, &b| acc << 8 | u64::from(b)),
+        Endian::Big => bytes.iter().fold(0, |acc, &b| acc << 8 | u64::from(b)),
     }
 }
 
+/// Formats `x` as GNU od does: like printf's `%g`, using the fewest
+/// significant digits, starting from `min_digits`, that read back
+/// (according to `round_trips`) as exactly the value printed.
+fn format_float<F: Fn(&str) -> bool>(
+    x: f64,
+    min_digits: usize,
+    max_digits: usize,
+    round_trips: F,
+) -> String {
+    if x.is_nan() {
+        return if x.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if x.is_infinite() {
+        return if x < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    let mut text = String::new();
+    for precision in min_digits..=max_digits {
+        // Rust's {:e} gives us the correctly rounded digits; all
+        // that's left is laying them out as %g would.
+        let scientific = format!("{:.*e}", precision - 1, x.abs());
+        let (mantissa, exponent) = scientific.split_once('e').unwrap();
+        let digits = mantissa.replace('.', "");
+        text = g_style(x.is_sign_negative(), &digits, exponent.parse().unwrap());
+        if round_trips(&text) {
+            break;
+        }
+    }
+    text
+}
+
+/// Lays out a number as printf's `%g` would, given its significant
+/// `digits` (as many as the precision) and the decimal `exponent` of
+/// the first of them.
+fn g_style(negative: bool, digits: &str, exponent: i32) -> String {
+    let mut text = String::from(if negative { "-" } else { "" });
+    if exponent < -4 || exponent >= digits.len() as i32 {
+        let (first, rest) = digits.split_at(1);
+        let rest = rest.trim_end_matches('0');
+        text.push_str(first);
+        if !rest.is_empty() {
+            text.push('.');
+            text.push_str(rest);
+        }
+        let sign = if exponent < 0 { '-' } else { '+' };
+        text.push_str(&format!("e{}{:02}", sign, exponent.abs()));
+    } else if exponent >= 0 {
+        let (int, fraction) = digits.split_at(exponent as usize + 1);
+        let fraction = fraction.trim_end_matches('0');
+        text.push_str(int);
+        if !fraction.is_empty() {
+            text.push('.');
+            text.push_str(fraction);
+        }
+    } else {
+        text.push_str("0.");
+        text.push_str(&"0".repeat((-exponent - 1) as usize));
+        text.push_str(digits.trim_end_matches('0'));
+    }
+    text
+}
+
+/// The values of --width and --strings are optional, which clap only
+/// allows if they're given with `=`. Their short forms take them as GNU
+/// od does (`-wBYTES`, and `-SBYTES` or `-S BYTES`), so those are
+/// passed on to clap in the long form, split off from any flags they
+/// follow in a cluster (`-vw8`). Operands, option values and anything
+/// after `--` are left alone.
+fn expand_args<I: IntoIterator<Item = OsString>>(args: I) -> Vec<OsString> {
+    let mut args = args.into_iter();
+    let mut expanded: Vec<OsString> = args.next().into_iter().collect();
+    while let Some(arg) = args.next() {
+        let arg = match arg.into_string() {
+            Ok(arg) => arg,
+            Err(arg) => {
+                expanded.push(arg);
+                continue;
+            }
+        };
+        if arg == "--" {
+            expanded.push(arg.into());
+            expanded.extend(args);
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            let value_follows = [
+                "address-radix",
+                "skip-bytes",
+                "read-bytes",
+                "format",
+                "endian",
+            ]
+            .contains(&long);
+            expanded.push(arg.into());
+            if value_follows {
+                expanded.extend(args.next());
+            }
+            continue;
+        }
+        let cluster = match arg.strip_prefix('-') {
+            Some(cluster) if !cluster.is_empty() => cluster,
+            _ => {
+                expanded.push(arg.into());
+                continue;
+            }
+        };
+
+        let mut value_follows = false;
+        let mut split = None;
+        for (i, flag) in cluster.char_indices() {
+            let (flags, rest) = (&cluster[..i], &cluster[i + flag.len_utf8()..]);
+            match flag {
+                'w' | 'S' => {
+                    let value = if !rest.is_empty() {
+                        Some(OsString::from(rest))
+                    } else if flag == 'S' {
+                        args.next()
+                    } else {
+                        None
+                    };
+                    let long = match (flag, value) {
+                        ('w', None) => OsString::from("--width"),
+                        ('S', None) => OsString::from("-S"),
+                        (_, Some(value)) => {
+                            let name = if flag == 'w' { "width" } else { "strings" };
+                            let mut long = OsString::from(format!("--{}=", name));
+                            long.push(value);
+                            long
+                        }
+                        _ => unreachable!(),
+                    };
+                    split = Some((flags, long));
+                    break;
+                }
+                'A' | 'j' | 'N' | 't' => {
+                    value_follows = rest.is_empty();
+                    break;
+                }
+                'a' | 'b' | 'c' | 'd' | 'f' | 'i' | 'l' | 'o' | 's' | 'x' | 'v' => (),
+                _ => break,
+            }
+        }
+        match split {
+            Some((flags, long)) => {
+                if !flags.is_empty() {
+                    expanded.push(format!("-{}", flags).into());
+                }
+                expanded.push(long);
+            }
+            None => {
+                expanded.push(arg.into());
+                if value_follows {
+                    expanded.extend(args.next());
+                }
+            }
+        }
+    }
+    expanded
+}
+
 fn main() {
+    let args = expand_args(env::args_os());
+
     let matches = App::new("rust-od")
         .version("0.1.0")
         .author("Jos V. <jos@josaphat.co>")
-        .about("Rust clone of the od utility. Very incomplete. Supports only the -A/--address-radix option")
+        .about("Rust clone of the od utility. Write an unambiguous representation, octal bytes by default, of FILE to standard output.")
         .arg(
             Arg::new("FILE")
                 .help("Input file to written to standard output in given formats")
@@ -77,7 +384,95 @@ fn main() {
                            .help("Select the base in which file offsets are printed. radix can be one of the following:\n\td - decimal,\n\to - octal,\n\tx - hexadecimal,\n\tn - none (do not print offsets).")
                            .default_value("o")
         )
-        .get_matches();
+        .arg(
+            Arg::new("skip_bytes")
+                .short('j')
+                .long("skip-bytes")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("skip BYTES input bytes first")
+        )
+        .arg(
+            Arg::new("read_bytes")
+                .short('N')
+                .long("read-bytes")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("limit dump to BYTES input bytes")
+        )
+        .arg(
+            Arg::new("strings")
+                .short('S')
+                .long("strings")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .default_missing_value("3")
+                .value_name("BYTES")
+                .help("show only NUL terminated strings of at least BYTES (3) printable characters")
+        )
+        .arg(
+            Arg::new("width")
+                .short('w')
+                .long("width")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .default_missing_value("32")
+                .value_name("BYTES")
+                .help("output BYTES bytes per output line; 32 is implied when BYTES is not specified")
+        )
+        .arg(
+            Arg::new("output_duplicates")
+                .short('v')
+                .long("output-duplicates")
+                .help("do not use * to mark line suppression")
+        )
+        .arg(
+            Arg::new("endian")
+                .long("endian")
+                .takes_value(true)
+                .value_name("order")
+                .help("swap input bytes according to the specified order: big or little (default: that of this machine)")
+        )
+        .arg(
+            Arg::new("format")
+                .short('t')
+                .long("format")
+                .takes_value(true)
+                .value_name("TYPE")
+                .multiple_occurrences(true)
+                .help("select output format or formats")
+        )
+        .arg(Arg::new("a").short('a').multiple_occurrences(true).help("same as -t a,  select named characters, ignoring high-order bit"))
+        .arg(Arg::new("b").short('b').multiple_occurrences(true).help("same as -t o1, select octal bytes"))
+        .arg(Arg::new("c").short('c').multiple_occurrences(true).help("same as -t c,  select printable characters or backslash escapes"))
+        .arg(Arg::new("d").short('d').multiple_occurrences(true).help("same as -t u2, select unsigned decimal 2-byte units"))
+        .arg(Arg::new("f").short('f').multiple_occurrences(true).help("same as -t fF, select floats"))
+        .arg(Arg::new("i").short('i').multiple_occurrences(true).help("same as -t dI, select decimal ints"))
+        .arg(Arg::new("l").short('l').multiple_occurrences(true).help("same as -t dL, select decimal longs"))
+        .arg(Arg::new("o").short('o').multiple_occurrences(true).help("same as -t o2, select octal 2-byte units"))
+        .arg(Arg::new("s").short('s').multiple_occurrences(true).help("same as -t d2, select decimal 2-byte units"))
+        .arg(Arg::new("x").short('x').multiple_occurrences(true).help("same as -t x2, select hexadecimal 2-byte units"))
+        .after_help("TYPE is made up of one or more of these specifications:\n\n\
+                     \ta\t\tnamed character, ignoring high-order bit\n\
+                     \tc\t\tprintable character or backslash escape\n\
+                     \td[SIZE]\t\tsigned decimal, SIZE bytes per integer\n\
+                     \tf[SIZE]\t\tfloating point, SIZE bytes per float\n\
+                     \to[SIZE]\t\toctal, SIZE bytes per integer\n\
+                     \tu[SIZE]\t\tunsigned decimal, SIZE bytes per integer\n\
+                     \tx[SIZE]\t\thexadecimal, SIZE bytes per integer\n\n\
+                     SIZE is a number. For TYPE in [doux], SIZE may also be C for sizeof(char), S for sizeof(short), \
+                     I for sizeof(int) or L for sizeof(long). If TYPE is f, SIZE may also be F for sizeof(float), \
+                     D for sizeof(double) or L for sizeof(long double).\n\n\
+                     Adding a z suffix to any type displays printable characters at the end of each output line.\n\n\
+                     BYTES is hex with 0x or 0X prefix, and may have a multiplier suffix: b 512, KB 1000, K 1024, \
+                     MB 1000*1000, M 1024*1024, and so on for G, T, P, E, Z, Y, R, Q. Binary prefixes can be used, \
+                     too: KiB=K, MiB=M, and so on.\n\n\
+                     The traditional form `od [FILE] [+]OFFSET[.][b]` is also accepted when none of -A, -j, -N, -S, \
+                     -t, -v, -w or --endian is given: OFFSET is octal, or decimal if followed by `.`, and counts 512-byte blocks \
+                     if followed by `b`.")
+        .get_matches_from(args);
 
     let address_radix = match matches.value_of("address_radix").unwrap_or_else(|| {
         eprintln!("invalid output address radix. must be one character from [doxn]");
@@ -96,139 +491,1073 @@ fn main() {
         }
     };
 
-    let width = 16; // number of bytes on a line.
-    let output_duplicates = true;
+    let endian = match matches.value_of("endian") {
+        None => Endian::native(),
+        Some("big") => Endian::Big,
+        Some("little") => Endian::Little,
+        Some(x) => {
+            eprintln!(
+                "invalid argument '{}' for '--endian'\nValid arguments are:\n  - 'big'\n  - 'little'",
+                x
+            );
+            process::exit(1);
+        }
+    };
+
+    // Formats are printed in the order they were given, whether with
+    // -t or one of the traditional single-letter options.
+    let mut type_strings: Vec<(usize, &str)> = vec![];
+    if let (Some(values), Some(indices)) =
+        (matches.values_of("format"), matches.indices_of("format"))
+    {
+        type_strings.extend(indices.zip(values));
+    }
+    for (flag, type_string) in [
+        ("a", "a"),
+        ("b", "o1"),
+        ("c", "c"),
+        ("d", "u2"),
+        ("f", "fF"),
+        ("i", "dI"),
+        ("l", "dL"),
+        ("o", "o2"),
+        ("s", "d2"),
+        ("x", "x2"),
+    ] {
+        if let Some(indices) = matches.indices_of(flag) {
+            type_strings.extend(indices.map(|i| (i, type_string)));
+        }
+    }
+    type_strings.sort_by_key(|&(i, _)| i);
 
+    let mut formats = vec![];
+    for (_, type_string) in type_strings {
+        formats.extend(parse_type_string(type_string).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        }));
+    }
+    let strings = matches
+        .value_of("strings")
+        .map(|val| parse_byte_count(val, "-S"));
+    if strings.is_some() && !formats.is_empty() {
+        eprintln!("no type may be specified when dumping strings");
+        process::exit(1);
+    }
     // The default format, if unspecified, is "oS". On a platform
     // where a 'short' is 16 bits, this is the same as "o2".
-    // let formats = vec![Format{ftype: FormatType::Octal, character_width: 2, is_display: false}];
-    let offset = 0;
+    if formats.is_empty() {
+        formats = parse_type_string("oS").unwrap();
+    }
+
+    // Lines hold a whole number of values of every format: by
+    // default the smallest such number of bytes, or as many times
+    // that as fit in 16.
+    let lcm = formats.iter().map(|fmt| fmt.character_width).fold(1, lcm);
+    let width = match matches.value_of("width") {
+        None if lcm < 16 => lcm * (16 / lcm),
+        None => lcm,
+        Some(val) => {
+            let width = val.parse::<usize>().unwrap_or_else(|_| {
+                eprintln!("invalid -w argument '{}'", val);
+                process::exit(1);
+            });
+            if width != 0 && width.is_multiple_of(lcm) {
+                width
+            } else {
+                eprintln!("warning: invalid width {}; using {} instead", width, lcm);
+                lcm
+            }
+        }
+    };
+
+    let mut skip_bytes = match matches.value_of("skip_bytes") {
+        Some(val) => parse_byte_count(val, "-j"),
+        None => 0,
+    };
+    let read_bytes = matches
+        .value_of("read_bytes")
+        .map(|val| parse_byte_count(val, "-N"));
 
     // Unwrap is fine here; FILE will have a default.
-    let files: Vec<_> = matches.values_of("FILE").unwrap().collect();
-    let res = od(
-        files,
-        offset,
-        output_duplicates,
+    let mut files: Vec<_> = matches.values_of("FILE").unwrap().collect();
+
+    // The traditional syntax, `od [FILE] [+]OFFSET[.][b]`, is only
+    // recognized when no option of the modern syntax is used.
+    let modern = matches.occurrences_of("address_radix") > 0
+        || [
+            "skip_bytes",
+            "read_bytes",
+            "format",
+            "strings",
+            "width",
+            "output_duplicates",
+            "endian",
+        ]
+        .iter()
+        .any(|&name| matches.is_present(name));
+    if !modern {
+        match files[..] {
+            [operand] if operand.starts_with('+') => {
+                if let Some(offset) = parse_old_offset(operand) {
+                    skip_bytes = offset;
+                    files = vec!["-"];
+                }
+            }
+            [file, operand] if operand.starts_with(|c: char| c == '+' || c.is_ascii_digit()) => {
+                if let Some(offset) = parse_old_offset(operand) {
+                    skip_bytes = offset;
+                    files = vec![file];
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let options = Options {
         address_radix,
-        Format {
-            // ftype: FormatType::Octal,
-            character_width: 2,
-            // is_display: false,
-        },
+        formats,
         width,
-    );
-    match res {
-        Err(error) => match error.kind() {
-            ErrorKind::BrokenPipe => (),
-            _ => panic!("Error: {}", error),
+        endian,
+        skip_bytes,
+        read_bytes,
+        output_duplicates: matches.is_present("output_duplicates"),
+        strings,
+    };
+    match od(files, &options) {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(err) => write_error(err),
+    }
+}
+
+/// Parses the TYPE argument of -t, which may hold several format
+/// specifications one after another (`x1z`, `d4f`, ...).
+fn parse_type_string(type_string: &str) -> Result<Vec<Format>, String> {
+    let bytes = type_string.as_bytes();
+    let mut formats = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let ftype = match bytes[i] {
+            b'a' => FormatType::NamedCharacter,
+            b'c' => FormatType::PrintableCharacter,
+            b'd' => FormatType::SignedDecimal,
+            b'f' => FormatType::FloatingPoint,
+            b'o' => FormatType::Octal,
+            b'u' => FormatType::UnsignedDecimal,
+            b'x' => FormatType::Hexadecimal,
+            _ => {
+                return Err(format!(
+                    "invalid character '{}' in type string '{}'",
+                    type_string[i..].chars().next().unwrap(),
+                    type_string
+                ))
+            }
+        };
+        i += 1;
+
+        let float = ftype == FormatType::FloatingPoint;
+        let character_width = match ftype {
+            FormatType::NamedCharacter | FormatType::PrintableCharacter => 1,
+            _ if bytes.get(i).is_some_and(u8::is_ascii_digit) => {
+                let len = bytes[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+                let size = type_string[i..i + len].parse().unwrap_or(usize::MAX);
+                i += len;
+                let supported: &[usize] = if float {
+                    &[4, 8, long_double::SIZE]
+                } else {
+                    &[1, 2, 4, 8]
+                };
+                if !supported.contains(&size) {
+                    return Err(format!(
+                        "invalid type string '{}';\nthis system doesn't provide a {}-byte {} type",
+                        type_string,
+                        &type_string[i - len..i],
+                        if float { "floating point" } else { "integral" }
+                    ));
+                }
+                size
+            }
+            _ => match bytes
+                .get(i)
+                .and_then(|&c| CharacterWidth::from_suffix(ftype, c))
+            {
+                Some(width) => {
+                    i += 1;
+                    width.bytes()
+                }
+                None if float => CharacterWidth::Double.bytes(),
+                None => CharacterWidth::Int.bytes(),
+            },
+        };
+
+        let show_chars = bytes.get(i) == Some(&b'z');
+        if show_chars {
+            i += 1;
+        }
+
+        formats.push(Format {
+            ftype,
+            character_width,
+            show_chars,
+        });
+    }
+    Ok(formats)
+}
+
+/// Parses the BYTES argument of -j or -N, exiting with an error
+/// message if it isn't valid.
+fn parse_byte_count(val: &str, option: &str) -> u64 {
+    parse_size(val, true).unwrap_or_else(|err| {
+        match err {
+            SizeError::Invalid => eprintln!("invalid {} argument '{}'", option, val),
+            SizeError::InvalidSuffix => {
+                eprintln!("invalid suffix in {} argument '{}'", option, val)
+            }
+            SizeError::Overflow => eprintln!("{} argument '{}' too large", option, val),
+        }
+        process::exit(1);
+    })
+}
+
+/// Parses the OFFSET operand of the traditional syntax: `[+]OFFSET[.][b]`,
+/// octal unless followed by `.` (or hexadecimal with a `0x` prefix),
+/// in 512-byte blocks if followed by `b`.
+fn parse_old_offset(s: &str) -> Option<u64> {
+    let s = s.strip_prefix('+').unwrap_or(s);
+    let (s, multiplier) = match s.strip_suffix('b') {
+        Some(s) => (s, 512),
+        None => (s, 1),
+    };
+    let (digits, radix) = match s.strip_suffix('.') {
+        Some(decimal) => (decimal, 10),
+        None => match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => (hex, 16),
+            None => (s, 8),
         },
-        _ => (),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    u64::from_str_radix(digits, radix)
+        .ok()?
+        .checked_mul(multiplier)
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        let r = x % y;
+        x = y;
+        y = r;
     }
+    a / x * b
 }
 
-// Instead of iterating over the files in a loop, we'll determine how
-// many bytes to read from a line,
-fn od(
-    files: Vec<&str>,
-    mut offset: usize,
-    _output_duplicates: bool,
-    addr_radix: AddressRadix,
-    fmt: Format,
+/// Settings taken from the command line.
+struct Options {
+    address_radix: AddressRadix,
+    formats: Vec<Format>,
+    /// Bytes of input per line.
     width: usize,
-) -> io::Result<()> {
-    let mut out = io::stdout();
+    endian: Endian,
+    skip_bytes: u64,
+    read_bytes: Option<u64>,
+    output_duplicates: bool,
+    /// -S: the minimum length of the strings to show, instead of
+    /// dumping the input.
+    strings: Option<u64>,
+}
 
-    let mut fs_iter = files.iter();
+// Instead of iterating over the files in a loop, we read the input
+// as one stream, a line's worth of bytes at a time. Returns whether
+// every file could be read; the only errors returned are those
+// writing the output.
+fn od(files: Vec<&str>, options: &Options) -> io::Result<bool> {
+    let Options {
+        address_radix: ref addr_radix,
+        ref formats,
+        width,
+        endian,
+        ..
+    } = *options;
 
-    let mut reader = open_reader(match fs_iter.next() {
-        Some(x) => x,
-        _ => return Ok(()),
-    });
+    let mut input = Input::new(&files);
+    // Like GNU od, print nothing at all if none of the files can be
+    // opened.
+    if input.reader().is_none() {
+        return Ok(false);
+    }
+    if input.skip(options.skip_bytes) > 0 {
+        eprintln!("cannot skip past end of combined input");
+        process::exit(1);
+    }
+    // Addresses are those in the input as a whole, skipped bytes
+    // included.
+    let mut offset = options.skip_bytes;
+    let mut remaining = options.read_bytes.unwrap_or(u64::MAX);
 
-    let mut end_of_input = false;
-    loop {
-        // Beginning of line
-        match addr_radix {
-            AddressRadix::Octal => write!(out, "{:07o}", offset)?,
-            AddressRadix::Hexadecimal => write!(out, "{:06x}", offset)?,
-            AddressRadix::Decimal => write!(out, "{:07}", offset)?,
-            AddressRadix::None => (),
-        };
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+
+    if let Some(min_len) = options.strings {
+        dump_strings(&mut input, &mut out, addr_radix, offset, remaining, min_len)?;
+        out.flush()?;
+        return Ok(!input.failed);
+    }
+
+    // So that lines for different formats line up, every format's
+    // line is made as wide as the widest one, with the extra space
+    // spread between its fields.
+    let line_width = formats
+        .iter()
+        .map(|fmt| (fmt.field_width() + 1) * (width / fmt.character_width))
+        .max()
+        .unwrap_or(0);
 
-        // The GNU version of od appears to dump one final beginning
-        // of line offest.
-        if end_of_input {
-            writeln!(out, "")?;
+    let mut block = vec![0; width];
+    // The previous line's bytes, unless this is the first, and whether
+    // it was left out as a repeat of the one before it.
+    let mut previous: Option<Vec<u8>> = None;
+    let mut suppressing = false;
+    loop {
+        let wanted = remaining.min(width as u64) as usize;
+        let n = input.fill(&mut block[..wanted])?;
+        if n == 0 {
             break;
         }
+        // Runs of identical lines are shown as the first of them
+        // followed by a line with just `*`.
+        if !options.output_duplicates && n == width && previous.as_ref() == Some(&block) {
+            if !suppressing {
+                writeln!(out, "*")?;
+                suppressing = true;
+            }
+            offset += n as u64;
+            remaining -= n as u64;
+            continue;
+        }
+        suppressing = false;
+        previous = Some(block.clone());
 
-        let line_reads = width / fmt.character_width;
+        // A short final block is zero-padded up to a whole value.
+        block[n..].iter_mut().for_each(|b| *b = 0);
 
-        for _i in 0..line_reads {
-            let mut int_bytes = vec![0; 2];
-            let mut n = reader.read(&mut int_bytes).unwrap();
-            if n == 0 {
-                // If we're at EOF, attempt to open the next file.
-                // If there is no next file, we're done.
-                reader = open_reader(match fs_iter.next() {
-                    Some(x) => x,
-                    _ => {
-                        end_of_input = true;
-                        break;
+        for (i, fmt) in formats.iter().enumerate() {
+            if i == 0 {
+                write_address(&mut out, addr_radix, offset)?;
+            } else {
+                write!(out, "{:1$}", "", address_width(addr_radix))?;
+            }
+            write_values(&mut out, fmt, &block, n, line_width, endian)?;
+            writeln!(out)?;
+        }
+        offset += n as u64;
+        remaining -= n as u64;
+    }
+
+    // The GNU version of od dumps one final offset: the size of the
+    // input.
+    if !matches!(*addr_radix, AddressRadix::None) {
+        write_address(&mut out, addr_radix, offset)?;
+        writeln!(out)?;
+    }
+    out.flush()?;
+    Ok(!input.failed)
+}
+
+/// Writes the strings in the input, for -S: runs of at least
+/// `min_len` printable characters ended by a null byte, or by the end
+/// of the bytes to be read if longer than `min_len`. Each is preceded
+/// by its address, which for the first byte read is `offset`.
+fn dump_strings<W: Write>(
+    input: &mut Input,
+    out: &mut W,
+    addr_radix: &AddressRadix,
+    mut offset: u64,
+    mut remaining: u64,
+    min_len: u64,
+) -> io::Result<()> {
+    let mut string = vec![];
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let wanted = remaining.min(buf.len() as u64) as usize;
+        let n = input.fill(&mut buf[..wanted])?;
+        if n == 0 {
+            break;
+        }
+        for &c in &buf[..n] {
+            match c {
+                0 => {
+                    if string.len() as u64 >= min_len {
+                        write_string(out, addr_radix, offset - string.len() as u64, &string)?;
                     }
-                });
-                continue;
-            } else if n < fmt.character_width {
-                // I'm being a bit naughty here... This loop is just
-                // so that I can break out if open_reader fails...
-                loop {
-                    reader = open_reader(match fs_iter.next() {
-                        Some(x) => x,
-                        _ => {
-                            end_of_input = true;
-                            break;
-                        }
-                    });
-                    break;
+                    string.clear();
                 }
+                0x20..=0x7e => string.push(c),
+                _ => string.clear(),
+            }
+            offset += 1;
+        }
+        remaining -= n as u64;
+    }
 
-                if !end_of_input {
-                    let mut bonus_byte = vec![0; 1];
-                    let bonus_n = reader.read(&mut bonus_byte).unwrap();
-                    if bonus_n == 0 {
-                        // An error, I think. We just opened this file.
-                        eprintln!("Error reading file");
-                        process::exit(1);
-                    }
-                    n += bonus_n;
-                    int_bytes[1] = bonus_byte[0];
-                } else {
-                    // We aren't going to get a full thing. Make the bonus byte a zero.
-                    int_bytes[1] = 0; // should be redundant
+    if remaining == 0 && string.len() as u64 > min_len {
+        write_string(out, addr_radix, offset - string.len() as u64, &string)?;
+    }
+    Ok(())
+}
+
+fn write_string<W: Write>(
+    out: &mut W,
+    addr_radix: &AddressRadix,
+    offset: u64,
+    string: &[u8],
+) -> io::Result<()> {
+    if !matches!(*addr_radix, AddressRadix::None) {
+        write_address(out, addr_radix, offset)?;
+        write!(out, " ")?;
+    }
+    out.write_all(string)?;
+    writeln!(out)
+}
+
+fn address_width(addr_radix: &AddressRadix) -> usize {
+    match addr_radix {
+        AddressRadix::Octal | AddressRadix::Decimal => 7,
+        AddressRadix::Hexadecimal => 6,
+        AddressRadix::None => 0,
+    }
+}
+
+fn write_address<W: Write>(out: &mut W, addr_radix: &AddressRadix, offset: u64) -> io::Result<()> {
+    match addr_radix {
+        AddressRadix::Octal => write!(out, "{:07o}", offset),
+        AddressRadix::Hexadecimal => write!(out, "{:06x}", offset),
+        AddressRadix::Decimal => write!(out, "{:07}", offset),
+        AddressRadix::None => Ok(()),
+    }
+}
+
+/// Writes one line's worth of values in format `fmt`, for the first
+/// `n` bytes of `block` (which is zero-padded past those).
+fn write_values<W: Write>(
+    out: &mut W,
+    fmt: &Format,
+    block: &[u8],
+    n: usize,
+    line_width: usize,
+    endian: Endian,
+) -> io::Result<()> {
+    let size = fmt.character_width;
+    let field_width = fmt.field_width();
+    let fields = block.len() / size;
+    let present = n.div_ceil(size);
+
+    // The padding is spread over the fields as GNU od does it, so
+    // that our columns match its.
+    let pad = line_width - field_width * fields;
+    let mut pad_remaining = pad;
+    for (i, value) in block.chunks(size).take(present).enumerate() {
+        let next_pad = pad * (fields - i - 1) / fields;
+        write!(
+            out,
+            "{:>1$}",
+            fmt.format_value(value, endian),
+            pad_remaining - next_pad + field_width
+        )?;
+        pad_remaining = next_pad;
+    }
+
+    if fmt.show_chars {
+        let blank_fields = fields - present;
+        let blank = blank_fields * field_width + pad * blank_fields / fields;
+        let chars: String = block[..n]
+            .iter()
+            .map(|&c| match c {
+                0x20..=0x7e => c as char,
+                _ => '.',
+            })
+            .collect();
+        write!(out, "{:1$}  >{2}<", "", blank, chars)?;
+    }
+    Ok(())
+}
+
+/// The FILE operands, read one after another as a single stream.
+///
+/// A file that can't be opened or read is reported and passed over,
+/// the stream carrying on with the next one.
+struct Input<'a> {
+    files: std::slice::Iter<'a, &'a str>,
+    /// The file being read, and its name.
+    reader: Option<(&'a str, Reader)>,
+    /// Whether any file couldn't be read.
+    failed: bool,
+}
+
+impl<'a> Input<'a> {
+    fn new(files: &'a [&'a str]) -> Input<'a> {
+        Input {
+            files: files.iter(),
+            reader: None,
+            failed: false,
+        }
+    }
+
+    /// The file being read, opening the next one if need be, or
+    /// `None` once they're all done.
+    fn reader(&mut self) -> Option<&Reader> {
+        while self.reader.is_none() {
+            let filename = *self.files.next()?;
+            match Reader::open(filename) {
+                Ok(reader) => self.reader = Some((filename, reader)),
+                Err(err) => {
+                    eprintln!("Error reading file `{}`: {}", filename, err);
+                    self.failed = true;
                 }
             }
+        }
+        self.reader.as_ref().map(|(_, reader)| reader)
+    }
 
-            let int = parse_i16(
-                int_bytes[0],
-                if n > 1 { int_bytes[1] } else { 0 },
-                Endian::Little,
-            );
-            write!(out, " {:06o}", int)?;
-            offset += n;
+    /// Reports an error reading the current file, and moves on to the
+    /// next one.
+    fn fail(&mut self, err: &io::Error) {
+        if let Some((filename, _)) = self.reader.take() {
+            eprintln!("Error reading file `{}`: {}", filename, err);
         }
-        writeln!(out, "")?;
+        self.failed = true;
+    }
+
+    /// Skips over the first `n` bytes of the input, seeking past them
+    /// where possible. Returns how many of them were left unskipped,
+    /// which is not zero only if the input is shorter than `n`.
+    fn skip(&mut self, mut n: u64) -> u64 {
+        while n > 0 {
+            let file = match self.reader() {
+                Some(reader) => &**reader,
+                None => break,
+            };
+            match skip_bytes(file, n) {
+                Ok(skipped) if skipped == n => return 0,
+                Ok(skipped) => {
+                    n -= skipped;
+                    self.reader = None;
+                }
+                Err(err) => self.fail(&err),
+            }
+        }
+        n
+    }
+
+    /// Fills `buf` from the input, carrying on into the next file
+    /// whenever one ends. Returns how many bytes were read, which is
+    /// less than `buf.len()` only once the input is exhausted.
+    fn fill(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let mut file = match self.reader() {
+                Some(reader) => &**reader,
+                None => break,
+            };
+            match file.read(&mut buf[filled..]) {
+                Ok(0) => self.reader = None,
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => self.fail(&err),
+            }
+        }
+        Ok(filled)
     }
-    Ok(())
 }
 
-fn open_reader(filename: &str) -> Box<dyn io::Read> {
-    if filename == "-" {
-        Box::new(io::stdin())
+/// Skips up to `n` bytes of `file`, returning how many there were.
+fn skip_bytes(mut file: &File, n: u64) -> io::Result<u64> {
+    // Some special files claim a small size they don't have, so only
+    // sizes beyond a block are trusted, as GNU od does.
+    let metadata = file.metadata()?;
+    if metadata.is_file() && metadata.size() > metadata.blksize() {
+        let left = metadata.size().saturating_sub(file.stream_position()?);
+        if n > left {
+            return Ok(left);
+        }
+        file.seek(SeekFrom::Current(n as i64))?;
+        Ok(n)
     } else {
-        Box::new(File::open(filename).unwrap_or_else(|err| {
-            eprintln!("Error reading file `{}`: {}", filename, err);
-            process::exit(1);
-        }))
+        io::copy(&mut file.take(n), &mut io::sink())
+    }
+}
+
+/// C's `long double`, for `-t fL`: the x87 80-bit extended format on
+/// x86, IEEE binary128 elsewhere. Rust has no such type, so values
+/// are printed by hand, using exact big-integer arithmetic to find
+/// the shortest digits that identify them, as is done for `f32` and
+/// `f64` above.
+mod long_double {
+    use super::Endian;
+    use std::cmp::Ordering;
+
+    /// Size of a `long double` in memory, padding included.
+    pub const SIZE: usize = 16;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    const DIG: usize = 18;
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    const DECIMAL_DIG: usize = 21;
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    const DIG: usize = 33;
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    const DECIMAL_DIG: usize = 36;
+
+    /// Sign, DECIMAL_DIG digits, point, and a four-digit exponent.
+    pub const FIELD_WIDTH: usize = 1 + DECIMAL_DIG + 1 + 2 + 4;
+
+    enum Value {
+        Nan(bool),
+        Infinite(bool),
+        Finite {
+            negative: bool,
+            /// The value is `mantissa` * 2^`exponent`.
+            mantissa: u128,
+            exponent: i32,
+            subnormal: bool,
+            /// At a power of two the next value down is only half as
+            /// far away as the next one up.
+            narrow_below: bool,
+        },
+    }
+
+    /// Decodes the x87 80-bit format, which has an explicit integer
+    /// bit. Encodings the FPU rejects count as NaN.
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn decode(bytes: &[u8; SIZE]) -> Value {
+        let mut mantissa_bytes = [0; 8];
+        mantissa_bytes.copy_from_slice(&bytes[..8]);
+        let mantissa = u64::from_le_bytes(mantissa_bytes);
+        let sign_exponent = u16::from_le_bytes([bytes[8], bytes[9]]);
+        let negative = sign_exponent >> 15 == 1;
+        let biased = i32::from(sign_exponent & 0x7fff);
+        let integer_bit = mantissa >> 63 == 1;
+
+        if biased == 0x7fff {
+            return if mantissa == 1 << 63 {
+                Value::Infinite(negative)
+            } else {
+                Value::Nan(negative)
+            };
+        }
+        if biased != 0 && !integer_bit {
+            return Value::Nan(negative);
+        }
+        Value::Finite {
+            negative,
+            mantissa: mantissa.into(),
+            exponent: biased.max(1) - 16383 - 63,
+            subnormal: biased == 0,
+            narrow_below: mantissa == 1 << 63 && biased > 1,
+        }
+    }
+
+    /// Decodes IEEE binary128.
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    fn decode(bytes: &[u8; SIZE]) -> Value {
+        let bits = u128::from_ne_bytes(*bytes);
+        let negative = bits >> 127 == 1;
+        let biased = ((bits >> 112) & 0x7fff) as i32;
+        let fraction = bits & ((1 << 112) - 1);
+
+        if biased == 0x7fff {
+            return if fraction == 0 {
+                Value::Infinite(negative)
+            } else {
+                Value::Nan(negative)
+            };
+        }
+        Value::Finite {
+            negative,
+            mantissa: if biased == 0 {
+                fraction
+            } else {
+                fraction | 1 << 112
+            },
+            exponent: biased.max(1) - 16383 - 112,
+            subnormal: biased == 0,
+            narrow_below: fraction == 0 && biased > 1,
+        }
+    }
+
+    pub fn format(bytes: &[u8], endian: Endian) -> String {
+        let mut native = [0; SIZE];
+        native.copy_from_slice(bytes);
+        if endian != Endian::native() {
+            native.reverse();
+        }
+
+        let (negative, mantissa, exponent, subnormal, narrow_below) = match decode(&native) {
+            Value::Nan(negative) => return if negative { "-nan" } else { "nan" }.to_string(),
+            Value::Infinite(negative) => return if negative { "-inf" } else { "inf" }.to_string(),
+            Value::Finite {
+                negative,
+                mantissa,
+                exponent,
+                subnormal,
+                narrow_below,
+            } => (negative, mantissa, exponent, subnormal, narrow_below),
+        };
+        if mantissa == 0 {
+            return if negative { "-0" } else { "0" }.to_string();
+        }
+
+        // All the digits of the exact value, and the decimal exponent
+        // of the first one.
+        let exact = if exponent >= 0 {
+            let mut n = Big::from(mantissa);
+            n.shl(exponent as u32);
+            n
+        } else {
+            let mut n = Big::from(mantissa);
+            n.mul_pow5(-exponent as u32);
+            n
+        };
+        let exact_digits = exact.to_decimal();
+        let first_exponent = exact_digits.len() as i32 - 1 + exponent.min(0);
+
+        let min_digits = if subnormal { 1 } else { DIG };
+        let mut text = String::new();
+        for precision in min_digits..=DECIMAL_DIG {
+            let (digits, digits_exponent) = round_digits(&exact_digits, first_exponent, precision);
+            text = super::g_style(negative, &digits, digits_exponent);
+            let last_exponent = digits_exponent - (precision as i32 - 1);
+            if round_trips(&digits, last_exponent, mantissa, exponent, narrow_below) {
+                break;
+            }
+        }
+        text
+    }
+
+    /// Rounds `digits`, the first of which has decimal exponent
+    /// `exponent`, to `precision` significant digits, ties to even.
+    /// Returns the new digits and the exponent of the first.
+    fn round_digits(digits: &str, mut exponent: i32, precision: usize) -> (String, i32) {
+        let digits = digits.as_bytes();
+        if digits.len() <= precision {
+            let mut kept = digits.to_vec();
+            kept.resize(precision, b'0');
+            return (String::from_utf8(kept).unwrap(), exponent);
+        }
+
+        let mut kept = digits[..precision].to_vec();
+        let rest = &digits[precision..];
+        let round_up = match rest[0] {
+            b'6'..=b'9' => true,
+            b'5' => rest[1..].iter().any(|&c| c != b'0') || kept[precision - 1] % 2 == 1,
+            _ => false,
+        };
+        if round_up {
+            match kept.iter().rposition(|&c| c != b'9') {
+                Some(i) => {
+                    kept[i] += 1;
+                    kept[i + 1..].iter_mut().for_each(|c| *c = b'0');
+                }
+                None => {
+                    kept.iter_mut().for_each(|c| *c = b'0');
+                    kept[0] = b'1';
+                    exponent += 1;
+                }
+            }
+        }
+        (String::from_utf8(kept).unwrap(), exponent)
+    }
+
+    /// Whether `digits` * 10^`digits_exponent` would be read back as
+    /// `mantissa` * 2^`exponent`: that is, whether it lies closer to
+    /// that value than to either of its neighbours, or exactly halfway
+    /// when the mantissa is even.
+    fn round_trips(
+        digits: &str,
+        digits_exponent: i32,
+        mantissa: u128,
+        exponent: i32,
+        narrow_below: bool,
+    ) -> bool {
+        // Everything is scaled by 4 to keep the halfway points whole.
+        let mut decimal = Big::from(0);
+        for c in digits.bytes() {
+            decimal.mul_small(10);
+            decimal.add_small(u32::from(c - b'0'));
+        }
+        decimal.mul_small(4);
+        let low = 4 * mantissa - if narrow_below { 1 } else { 2 };
+        let high = 4 * mantissa + 2;
+        let even = mantissa.is_multiple_of(2);
+
+        let above_low = compare(&decimal, digits_exponent, low, exponent);
+        let below_high = compare(&decimal, digits_exponent, high, exponent);
+        (above_low == Ordering::Greater || (even && above_low == Ordering::Equal))
+            && (below_high == Ordering::Less || (even && below_high == Ordering::Equal))
+    }
+
+    /// Compares `decimal` * 10^`decimal_exponent` with `binary` *
+    /// 2^`binary_exponent`, after scaling both so that every power
+    /// involved is a whole number.
+    fn compare(
+        decimal: &Big,
+        decimal_exponent: i32,
+        binary: u128,
+        binary_exponent: i32,
+    ) -> Ordering {
+        let min_twos = decimal_exponent.min(binary_exponent);
+        let extra_fives = (-decimal_exponent).max(0);
+
+        let mut left = decimal.clone();
+        left.shl((decimal_exponent - min_twos) as u32);
+        left.mul_pow5((decimal_exponent + extra_fives) as u32);
+
+        let mut right = Big::from(binary);
+        right.shl((binary_exponent - min_twos) as u32);
+        right.mul_pow5(extra_fives as u32);
+
+        left.cmp(&right)
+    }
+
+    /// Just enough of an arbitrary-precision unsigned integer for the
+    /// above: base 2^32 digits, least significant first, with no
+    /// leading zeros.
+    #[derive(Clone, PartialEq, Eq)]
+    struct Big(Vec<u32>);
+
+    impl From<u128> for Big {
+        fn from(mut n: u128) -> Big {
+            let mut limbs = vec![];
+            while n != 0 {
+                limbs.push(n as u32);
+                n >>= 32;
+            }
+            Big(limbs)
+        }
+    }
+
+    impl Big {
+        fn mul_small(&mut self, m: u32) {
+            let mut carry = 0u64;
+            for limb in self.0.iter_mut() {
+                let product = u64::from(*limb) * u64::from(m) + carry;
+                *limb = product as u32;
+                carry = product >> 32;
+            }
+            if carry != 0 {
+                self.0.push(carry as u32);
+            }
+            self.trim();
+        }
+
+        fn add_small(&mut self, a: u32) {
+            let mut carry = u64::from(a);
+            for limb in self.0.iter_mut() {
+                if carry == 0 {
+                    return;
+                }
+                let sum = u64::from(*limb) + carry;
+                *limb = sum as u32;
+                carry = sum >> 32;
+            }
+            if carry != 0 {
+                self.0.push(carry as u32);
+            }
+        }
+
+        fn mul_pow5(&mut self, mut n: u32) {
+            // 5^13 is the largest power of five that fits in a u32.
+            while n >= 13 {
+                self.mul_small(1_220_703_125);
+                n -= 13;
+            }
+            self.mul_small(5u32.pow(n));
+        }
+
+        fn shl(&mut self, bits: u32) {
+            if self.0.is_empty() {
+                return;
+            }
+            let limbs = (bits / 32) as usize;
+            let bits = bits % 32;
+            if bits != 0 {
+                let mut carry = 0;
+                for limb in self.0.iter_mut() {
+                    let shifted = (u64::from(*limb) << bits) | carry;
+                    *limb = shifted as u32;
+                    carry = shifted >> 32;
+                }
+                if carry != 0 {
+                    self.0.push(carry as u32);
+                }
+            }
+            self.0.splice(0..0, std::iter::repeat_n(0, limbs));
+        }
+
+        /// Divides in place, returning the remainder.
+        fn div_small(&mut self, d: u32) -> u32 {
+            let mut remainder = 0u64;
+            for limb in self.0.iter_mut().rev() {
+                let dividend = (remainder << 32) | u64::from(*limb);
+                *limb = (dividend / u64::from(d)) as u32;
+                remainder = dividend % u64::from(d);
+            }
+            self.trim();
+            remainder as u32
+        }
+
+        fn trim(&mut self) {
+            while self.0.last() == Some(&0) {
+                self.0.pop();
+            }
+        }
+
+        fn to_decimal(&self) -> String {
+            let mut n = self.clone();
+            let mut chunks = vec![];
+            while !n.0.is_empty() {
+                chunks.push(n.div_small(1_000_000_000));
+            }
+            let mut text = chunks.pop().map_or("0".to_string(), |c| c.to_string());
+            for chunk in chunks.iter().rev() {
+                text.push_str(&format!("{:09}", chunk));
+            }
+            text
+        }
+    }
+
+    impl Ord for Big {
+        fn cmp(&self, other: &Big) -> Ordering {
+            self.0
+                .len()
+                .cmp(&other.0.len())
+                .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+        }
+    }
+
+    impl PartialOrd for Big {
+        fn partial_cmp(&self, other: &Big) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// An x87 value from its 64-bit mantissa and its sign and
+        /// biased exponent, laid out little-endian as in memory.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        fn x87(mantissa: u64, sign_exponent: u16) -> String {
+            let mut bytes = [0; SIZE];
+            bytes[..8].copy_from_slice(&mantissa.to_le_bytes());
+            bytes[8..10].copy_from_slice(&sign_exponent.to_le_bytes());
+            format(&bytes, Endian::Little)
+        }
+
+        #[test]
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        fn formats_x87_values() {
+            assert_eq!(x87(1 << 63, 0x3fff), "1");
+            assert_eq!(x87(1 << 63, 0xbfff), "-1");
+            assert_eq!(x87(0xcccc_cccc_cccc_cccd, 0x3ffb), "0.1");
+            assert_eq!(x87(0, 0), "0");
+            assert_eq!(x87(0, 0x8000), "-0");
+            // The smallest subnormal, the largest one, and the smallest
+            // normal value just above it.
+            assert_eq!(x87(1, 0), "4e-4951");
+            assert_eq!(x87(u64::MAX >> 1, 0), "3.362103143112093506e-4932");
+            assert_eq!(x87(1 << 63, 1), "3.3621031431120935063e-4932");
+            assert_eq!(x87(u64::MAX, 0x7ffe), "1.189731495357231765e+4932");
+        }
+
+        #[test]
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        fn formats_x87_specials() {
+            assert_eq!(x87(1 << 63, 0x7fff), "inf");
+            assert_eq!(x87(1 << 63, 0xffff), "-inf");
+            assert_eq!(x87(3 << 62, 0x7fff), "nan");
+            assert_eq!(x87(3 << 62, 0xffff), "-nan");
+            // Without the integer bit, a normal exponent is invalid.
+            assert_eq!(x87(1 << 62, 0x3fff), "nan");
+        }
+
+        #[test]
+        fn round_digits_ties_to_even() {
+            assert_eq!(round_digits("125", 0, 2), ("12".to_string(), 0));
+            assert_eq!(round_digits("135", 0, 2), ("14".to_string(), 0));
+            assert_eq!(round_digits("1251", 0, 2), ("13".to_string(), 0));
+            assert_eq!(round_digits("124", 3, 2), ("12".to_string(), 3));
+            assert_eq!(round_digits("7", -2, 3), ("700".to_string(), -2));
+        }
+
+        #[test]
+        fn round_digits_carries_out_of_nines() {
+            assert_eq!(round_digits("996", 0, 2), ("10".to_string(), 1));
+            assert_eq!(round_digits("9950", 5, 2), ("10".to_string(), 6));
+            assert_eq!(round_digits("9949", 5, 2), ("99".to_string(), 5));
+        }
+
+        #[test]
+        fn round_trips_at_halfway_points() {
+            // 2's neighbours are 1 and 3, so 1.5 and 2.5 are the
+            // halfway points, which read back as 2 since it's even.
+            assert!(round_trips("15", -1, 2, 0, false));
+            assert!(round_trips("25", -1, 2, 0, false));
+            assert!(!round_trips("14", -1, 2, 0, false));
+            // 3 is odd, so 2.5 reads back as 2 instead.
+            assert!(!round_trips("25", -1, 3, 0, false));
+            assert!(round_trips("26", -1, 3, 0, false));
+        }
+
+        #[test]
+        fn round_trips_below_a_power_of_two() {
+            // 4 * 2^0 at a power of two: the next value down is 3.5,
+            // putting the halfway point at 3.75 rather than 3.5.
+            assert!(round_trips("375", -2, 4, 0, true));
+            assert!(!round_trips("37", -1, 4, 0, true));
+            assert!(round_trips("37", -1, 4, 0, false));
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(args: &[&str]) -> Vec<OsString> {
+        expand_args(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn expand_args_stops_at_double_dash() {
+        assert_eq!(expand(&["od", "--", "-wfoo"]), ["od", "--", "-wfoo"]);
+        assert_eq!(
+            expand(&["od", "-w4", "--", "-S2"]),
+            ["od", "--width=4", "--", "-S2"]
+        );
+    }
+
+    #[test]
+    fn expand_args_splits_clusters() {
+        assert_eq!(expand(&["od", "-vw8", "f"]), ["od", "-v", "--width=8", "f"]);
+        assert_eq!(expand(&["od", "-bw", "f"]), ["od", "-b", "--width", "f"]);
+        assert_eq!(
+            expand(&["od", "-vS", "4", "f"]),
+            ["od", "-v", "--strings=4", "f"]
+        );
+        assert_eq!(expand(&["od", "-cS5"]), ["od", "-c", "--strings=5"]);
+    }
+
+    #[test]
+    fn expand_args_leaves_values_alone() {
+        assert_eq!(expand(&["od", "-j", "-w2"]), ["od", "-j", "-w2"]);
+        assert_eq!(expand(&["od", "-tx1", "-w2"]), ["od", "-tx1", "--width=2"]);
+        assert_eq!(
+            expand(&["od", "--format", "-S3"]),
+            ["od", "--format", "-S3"]
+        );
+        assert_eq!(expand(&["od", "-", "file"]), ["od", "-", "file"]);
     }
 }
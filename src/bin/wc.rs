@@ -1,15 +1,44 @@
 //! wc - word, line, and byte or character count
 
 use clap::{App, Arg};
+use coreutils_rs::input::Input;
+use coreutils_rs::write_error;
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fs;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::os::unix::fs::MetadataExt;
+use std::process;
+use std::str;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use unicode_width::UnicodeWidthChar;
 
+/// Size of the buffer input is read into.
+const BUF_SIZE: usize = 128 * 1024;
+
+#[derive(Default)]
 struct Stats {
-    bytes: usize,
-    chars: usize,
-    words: usize,
-    newlines: usize,
-    max_line: usize,
+    bytes: u64,
+    chars: u64,
+    words: u64,
+    newlines: u64,
+    max_line: u64,
+}
+
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        self.bytes += other.bytes;
+        self.chars += other.chars;
+        self.words += other.words;
+        self.newlines += other.newlines;
+        self.max_line = self.max_line.max(other.max_line);
+    }
 }
 
 fn main() {
@@ -23,6 +52,7 @@ fn main() {
                 .required(false)
                 .default_value("-")
                 .multiple_occurrences(true)
+                .allow_invalid_utf8(true)
         )
         .arg(
             Arg::new("bytes")
@@ -58,154 +88,942 @@ fn main() {
                 .long("max-line-length")
                 .help("print the maximum display width")
         )
+        .arg(
+            Arg::new("files0_from")
+                .long("files0-from")
+                .takes_value(true)
+                .value_name("F")
+                .allow_invalid_utf8(true)
+                .help("read input from the files specified by NUL-terminated names in file F; If F is - then read names from standard input")
+        )
+        .arg(
+            Arg::new("total")
+                .long("total")
+                .takes_value(true)
+                .value_name("WHEN")
+                .help("when to print a line with total counts; WHEN can be: auto, always, only, never")
+        )
         .after_help("This application is free software.")
         .get_matches();
 
-    let bytes_arg = matches.is_present("bytes");
-    let mut chars_arg = matches.is_present("chars");
+    let mut bytes_arg = matches.is_present("bytes");
+    let chars_arg = matches.is_present("chars");
     let mut words_arg = matches.is_present("words");
     let mut lines_arg = matches.is_present("lines");
     let max_line_arg = matches.is_present("max_line_length");
 
     // If none are specified it's as if '-clw' were specified
     if !bytes_arg && !chars_arg && !words_arg && !lines_arg && !max_line_arg {
-        chars_arg = true;
+        bytes_arg = true;
         lines_arg = true;
         words_arg = true;
     }
-    let chars_arg = chars_arg;
+    let bytes_arg = bytes_arg;
     let lines_arg = lines_arg;
     let words_arg = words_arg;
 
-    let files: Vec<_> = matches.values_of("file").unwrap().collect();
-    let files_len = files.len();
+    let total = match matches.value_of("total").unwrap_or("auto") {
+        "auto" => Total::Auto,
+        "always" => Total::Always,
+        "only" => Total::Only,
+        "never" => Total::Never,
+        when => {
+            eprintln!(
+                "invalid argument '{}' for '--total'\nValid arguments are:\n  - 'auto'\n  - 'always'\n  - 'only'\n  - 'never'",
+                when
+            );
+            process::exit(1);
+        }
+    };
 
-    let mut files_stats = vec![];
+    // The names of the files to count come either from the command
+    // line or, with --files0-from, from a list of null-terminated
+    // names. A list in a regular file is read in full first, like the
+    // command line, so that the columns can be sized to the files, as
+    // GNU wc does; any other list is read as we go.
+    let files_from = matches.value_of_os("files0_from");
+    let mut listed_upfront = true;
+    // With no names at all, standard input is counted, but unnamed.
+    let unnamed = files_from.is_none() && matches.occurrences_of("file") == 0;
+    let mut names: Box<dyn Iterator<Item = io::Result<Vec<u8>>>> = match files_from {
+        Some(list) => {
+            if matches.occurrences_of("file") > 0 {
+                let operand = matches.value_of_os("file").unwrap();
+                eprintln!(
+                    "extra operand '{}'\nfile operands cannot be combined with --files0-from",
+                    operand.to_string_lossy()
+                );
+                process::exit(1);
+            }
+            let list: Box<dyn Read> = if list == "-" {
+                let stdin = Input::stdin();
+                listed_upfront = stdin.metadata().is_ok_and(|m| m.is_file());
+                Box::new(io::stdin())
+            } else {
+                let file = File::open(list).unwrap_or_else(|err| {
+                    eprintln!(
+                        "cannot open '{}' for reading: {}",
+                        list.to_string_lossy(),
+                        err
+                    );
+                    process::exit(1);
+                });
+                listed_upfront = file.metadata().is_ok_and(|m| m.is_file());
+                Box::new(file)
+            };
+            Box::new(BufReader::new(list).split(0))
+        }
+        // Unwrap is fine here; FILE will have a default.
+        None => Box::new(
+            matches
+                .values_of_os("file")
+                .unwrap()
+                .map(|name| Ok(name.as_bytes().to_vec())),
+        ),
+    };
 
-    let mut bytes_tot = 0;
-    let mut chars_tot = 0;
-    let mut words_tot = 0;
-    let mut newlines_tot = 0;
-    let mut maxest_line = 0;
+    let mut status = 0;
 
-    for filename in files {
-        let reader: Box<dyn BufRead> = if filename == "-" {
-            Box::new(BufReader::new(std::io::stdin()))
+    let fields = Fields {
+        lines: lines_arg,
+        words: words_arg,
+        chars: chars_arg,
+        bytes: bytes_arg,
+        max_line: max_line_arg,
+    };
+    let nfields = [lines_arg, words_arg, chars_arg, bytes_arg, max_line_arg]
+        .iter()
+        .filter(|&&field| field)
+        .count();
+
+    // Every count is as wide as the total size of the files, which none
+    // can exceed, and at least 7 wide if any isn't a regular file, as
+    // there's no telling beforehand how much it holds. A lone count of
+    // a lone file, or of names not known upfront, isn't padded at all.
+    let mut width = 1;
+    if listed_upfront {
+        let mut listed = vec![];
+        for name in names {
+            match name {
+                Ok(name) => listed.push(name),
+                Err(err) => {
+                    let list = files_from.unwrap().to_string_lossy();
+                    eprintln!("Error reading file `{}`: {}", list, err);
+                    status = 1;
+                    break;
+                }
+            }
+        }
+        if listed.len() > 1 || (listed.len() == 1 && nfields > 1) {
+            let metadata: Vec<_> = listed.iter().map(|name| metadata(name)).collect();
+            width = number_width(&metadata);
+        }
+        names = Box::new(listed.into_iter().map(Ok));
+    }
+
+    let mode = if chars_arg || words_arg || max_line_arg {
+        Mode::All
+    } else if lines_arg {
+        Mode::Lines
+    } else {
+        Mode::Bytes
+    };
+
+    let mut report = Report {
+        out: io::BufWriter::new(io::stdout()),
+        fields,
+        width,
+        show_files: total != Total::Only,
+        pending: VecDeque::new(),
+        first: 0,
+        total: Stats::default(),
+        failed: false,
+    };
+    let mut nfiles = 0;
+
+    // Files are counted on a pool of worker threads, big ones a chunk
+    // on each, while the names are read and the files opened here, in
+    // order. The queue is kept short, so that there are never many
+    // files open at once. The counts are put back in order and written
+    // out on a thread of their own, each file's as soon as it's done.
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let (job_sender, job_receiver) = mpsc::sync_channel::<Job>(2 * threads);
+    let job_receiver = Mutex::new(job_receiver);
+    let (event_sender, event_receiver) = mpsc::channel();
+
+    let mut report = thread::scope(|scope| {
+        let collector = scope.spawn(move || {
+            report.collect(event_receiver);
+            report
+        });
+        for _ in 0..threads {
+            let job_receiver = &job_receiver;
+            let event_sender = event_sender.clone();
+            scope.spawn(move || loop {
+                let job = match job_receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                let res = job.run(mode);
+                let _ = event_sender.send(Event::Chunk(job.file, job.chunk, res));
+            });
+        }
+
+        // Every name makes one entry in the report, whether a file's
+        // counts or just a diagnostic; `file` numbers them.
+        for (file, filename) in names.enumerate() {
+            let filename = match filename {
+                Ok(filename) => filename,
+                Err(err) => {
+                    let list = files_from.unwrap().to_string_lossy();
+                    let message = format!("Error reading file `{}`: {}", list, err);
+                    event_sender.send(Event::Skip(message)).unwrap();
+                    break;
+                }
+            };
+            nfiles = file + 1;
+            if let Some(list) = files_from {
+                if filename.is_empty() {
+                    let message = format!(
+                        "{}:{}: invalid zero-length file name",
+                        list.to_string_lossy(),
+                        file + 1
+                    );
+                    event_sender.send(Event::Skip(message)).unwrap();
+                    continue;
+                }
+                if list == "-" && filename == b"-" {
+                    let message = "when reading file names from stdin, no file name of '-' allowed";
+                    event_sender.send(Event::Skip(message.to_string())).unwrap();
+                    continue;
+                }
+            }
+
+            let path = OsStr::from_bytes(&filename);
+            let input = if filename == b"-" {
+                Input::stdin()
+            } else {
+                match File::open(path) {
+                    Ok(file) => Input::File(file),
+                    Err(err) => {
+                        let message =
+                            format!("Error reading file `{}`: {}", path.to_string_lossy(), err);
+                        event_sender.send(Event::Skip(message)).unwrap();
+                        continue;
+                    }
+                }
+            };
+            let name = if unnamed { None } else { Some(filename) };
+
+            let input = Arc::new(input);
+            match chunks(&input, mode, threads) {
+                Ok(Some(ranges)) => {
+                    event_sender.send(Event::File(name, ranges.len())).unwrap();
+                    for (chunk, &range) in ranges.iter().enumerate() {
+                        let input = Arc::clone(&input);
+                        let range = Some(range);
+                        job_sender
+                            .send(Job {
+                                file,
+                                chunk,
+                                input,
+                                range,
+                            })
+                            .unwrap();
+                    }
+                }
+                Ok(None) => {
+                    event_sender.send(Event::File(name, 1)).unwrap();
+                    let job = Job {
+                        file,
+                        chunk: 0,
+                        input,
+                        range: None,
+                    };
+                    // Standard input is read here, so that when it's
+                    // named more than once, the first one reads it all.
+                    if let Input::Stdin(_) = *job.input {
+                        let res = job.run(mode);
+                        event_sender.send(Event::Chunk(file, 0, res)).unwrap();
+                    } else {
+                        job_sender.send(job).unwrap();
+                    }
+                }
+                Err(err) => {
+                    event_sender.send(Event::File(name, 1)).unwrap();
+                    event_sender.send(Event::Chunk(file, 0, Err(err))).unwrap();
+                }
+            }
+        }
+        drop(job_sender);
+        drop(event_sender);
+        collector.join().unwrap()
+    });
+
+    let show_total = match total {
+        Total::Auto => nfiles > 1,
+        Total::Always | Total::Only => true,
+        Total::Never => false,
+    };
+    if show_total {
+        let name: Option<&[u8]> = if total == Total::Only {
+            None
         } else {
-            Box::new(BufReader::new(File::open(filename).unwrap()))
+            Some(b"total")
         };
-        let res = wordcount(reader);
-
-        bytes_tot += res.bytes;
-        chars_tot += res.chars;
-        words_tot += res.words;
-        newlines_tot += res.newlines;
-        if res.max_line > maxest_line {
-            maxest_line = res.max_line;
-        }
-
-        files_stats.push((filename, res));
-    }
-    if files_len > 1 {
-        files_stats.push((
-            "total",
-            Stats {
-                bytes: bytes_tot,
-                chars: chars_tot,
-                words: words_tot,
-                newlines: newlines_tot,
-                max_line: maxest_line,
-            },
-        ));
+        let total = mem::take(&mut report.total);
+        report.write(name, &total);
+    }
+    report.flush();
+    if report.failed {
+        status = 1;
+    }
+    process::exit(status);
+}
+
+/// Reports on the files in the order they were named, though their
+/// counts come from the worker threads in any order: each one's line,
+/// or diagnostic, is written as soon as it and every one before it
+/// are done.
+struct Report {
+    out: io::BufWriter<io::Stdout>,
+    fields: Fields,
+    width: usize,
+    /// False with --total=only.
+    show_files: bool,
+    /// The entries not yet reported on, the first of them numbered
+    /// `first`.
+    pending: VecDeque<Entry>,
+    first: usize,
+    total: Stats,
+    /// Whether any file couldn't be counted.
+    failed: bool,
+}
+
+/// What the reading of names and the counting send to the `Report`.
+enum Event {
+    /// The next file, with the number of chunks it's counted in.
+    File(Option<Vec<u8>>, usize),
+    /// A diagnostic for the next name, which isn't counted.
+    Skip(String),
+    /// The counts of a chunk of a file, by entry and chunk number.
+    Chunk(usize, usize, io::Result<Counter>),
+}
+
+enum Entry {
+    File(Option<Vec<u8>>, Vec<Option<io::Result<Counter>>>),
+    Skip(String),
+}
+
+/// Which counts to print, in the order they're printed.
+struct Fields {
+    lines: bool,
+    words: bool,
+    chars: bool,
+    bytes: bool,
+    max_line: bool,
+}
+
+impl Report {
+    fn collect(&mut self, events: mpsc::Receiver<Event>) {
+        loop {
+            let event = match events.try_recv() {
+                Ok(event) => event,
+                Err(mpsc::TryRecvError::Empty) => {
+                    // Let out what's written so far while we wait.
+                    self.flush();
+                    match events.recv() {
+                        Ok(event) => event,
+                        Err(_) => break,
+                    }
+                }
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            };
+            match event {
+                Event::File(name, chunks) => {
+                    let counters = (0..chunks).map(|_| None).collect();
+                    self.pending.push_back(Entry::File(name, counters));
+                }
+                Event::Skip(message) => self.pending.push_back(Entry::Skip(message)),
+                Event::Chunk(file, chunk, res) => {
+                    if let Entry::File(_, counters) = &mut self.pending[file - self.first] {
+                        counters[chunk] = Some(res);
+                    }
+                }
+            }
+            self.report_ready();
+        }
     }
 
-    // Use the total for the bytes and make the width of the columns
-    // all equal.  Bytes is the smallest unit, so it's going to be the
-    // largest number.
-    let col_width = bytes_tot.to_string().len();
+    /// Reports on the entries at the front that are done.
+    fn report_ready(&mut self) {
+        while let Some(entry) = self.pending.front() {
+            if let Entry::File(_, counters) = entry {
+                if counters.iter().any(Option::is_none) {
+                    break;
+                }
+            }
+            self.first += 1;
+            match self.pending.pop_front().unwrap() {
+                Entry::Skip(message) => {
+                    self.flush();
+                    eprintln!("{}", message);
+                    self.failed = true;
+                }
+                Entry::File(name, counters) => {
+                    // A file that can't be read is still listed, with
+                    // no counts.
+                    let stats = match counters.into_iter().map(Option::unwrap).collect() {
+                        Ok(counters) => merge::<Vec<_>>(counters),
+                        Err(err) => {
+                            self.flush();
+                            let name = name.as_deref().unwrap_or(b"-");
+                            eprintln!(
+                                "Error reading file `{}`: {}",
+                                String::from_utf8_lossy(name),
+                                err
+                            );
+                            self.failed = true;
+                            Stats::default()
+                        }
+                    };
+                    self.total.add(&stats);
+                    if self.show_files {
+                        self.write(name.as_deref(), &stats);
+                    }
+                }
+            }
+        }
+    }
 
-    for stats in files_stats {
-        if lines_arg {
-            print!("{:1$} ", stats.1.newlines, col_width);
+    /// Writes a line of counts, exiting if standard output fails.
+    fn write(&mut self, name: Option<&[u8]>, stats: &Stats) {
+        if let Err(err) = self.write_counts(name, stats) {
+            write_error(err);
         }
-        if words_arg {
-            print!("{:1$} ", stats.1.words, col_width);
+    }
+
+    fn write_counts(&mut self, name: Option<&[u8]>, stats: &Stats) -> io::Result<()> {
+        let mut counts = vec![];
+        if self.fields.lines {
+            counts.push(stats.newlines);
+        }
+        if self.fields.words {
+            counts.push(stats.words);
         }
-        if chars_arg {
-            print!("{:1$} ", stats.1.chars, col_width);
+        if self.fields.chars {
+            counts.push(stats.chars);
         }
-        if bytes_arg {
-            print!("{:1$} ", stats.1.bytes, col_width);
+        if self.fields.bytes {
+            counts.push(stats.bytes);
         }
-        if max_line_arg {
-            print!("{:1$} ", stats.1.max_line, col_width);
+        if self.fields.max_line {
+            counts.push(stats.max_line);
+        }
+        for (i, count) in counts.iter().enumerate() {
+            if i > 0 {
+                write!(self.out, " ")?;
+            }
+            write!(self.out, "{:1$}", count, self.width)?;
+        }
+        if let Some(name) = name {
+            write!(self.out, " ")?;
+            if name.contains(&b'\n') {
+                self.out.write_all(&quote(name))?;
+            } else {
+                self.out.write_all(name)?;
+            }
+        }
+        writeln!(self.out)
+    }
+
+    fn flush(&mut self) {
+        if let Err(err) = self.out.flush() {
+            write_error(err);
         }
-        println!("{}", if stats.0 != "-" { stats.0 } else { "" });
     }
 }
 
-/// Computes and returns statistics for the text readable from the
-/// given reader. The given reader is read until the EOF condition is
-/// reached.
-///
-/// When reporting the number of characters, this does not take into
-/// account "grapheme clusters," but it does consider individual code
-/// points.
-fn wordcount(mut reader: Box<dyn BufRead>) -> Stats {
-    // Keep running counts of the values we care about
+/// Looks up a name for sizing the columns, standard input by its
+/// descriptor.
+fn metadata(name: &[u8]) -> Option<fs::Metadata> {
+    if name == b"-" {
+        Input::stdin().metadata().ok()
+    } else {
+        fs::metadata(OsStr::from_bytes(name)).ok()
+    }
+}
+
+/// The width of the widest count there can be: that of the total size
+/// of the regular files, but 7 at least if any input is something else.
+/// Names that couldn't be looked up don't count.
+fn number_width(metadata: &[Option<fs::Metadata>]) -> usize {
+    let mut minimum = 1;
+    let mut total = 0;
+    for metadata in metadata.iter().flatten() {
+        if metadata.is_file() {
+            total += metadata.len();
+        } else {
+            minimum = 7;
+        }
+    }
+    total.to_string().len().max(minimum)
+}
+
+/// Quotes a file name with a newline in it, as GNU wc does, so that it
+/// can't pass for more than one line of output: in single quotes, with
+/// control characters and invalid UTF-8 escaped in `$'...'`.
+fn quote(name: &[u8]) -> Vec<u8> {
+    let mut quoted = b"'".to_vec();
+    let mut escaping = false;
+    let mut rest = name;
+    while !rest.is_empty() {
+        let (valid, bad) = match str::from_utf8(rest) {
+            Ok(valid) => (valid, 0),
+            Err(err) => (
+                str::from_utf8(&rest[..err.valid_up_to()]).unwrap(),
+                err.error_len().unwrap_or(rest.len() - err.valid_up_to()),
+            ),
+        };
+        for c in valid.chars() {
+            let mut buf = [0; 4];
+            let bytes = c.encode_utf8(&mut buf).as_bytes();
+            if c.is_control() {
+                escape(&mut quoted, &mut escaping, bytes);
+                continue;
+            }
+            if escaping {
+                quoted.extend_from_slice(b"''");
+                escaping = false;
+            }
+            if c == '\'' {
+                quoted.extend_from_slice(b"'\\''");
+            } else {
+                quoted.extend_from_slice(bytes);
+            }
+        }
+        let end = valid.len() + bad;
+        escape(&mut quoted, &mut escaping, &rest[valid.len()..end]);
+        rest = &rest[end..];
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/// Adds `bytes` to a quoted name escaped, opening an `$'...'` for them
+/// unless one is open already.
+fn escape(quoted: &mut Vec<u8>, escaping: &mut bool, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    if !*escaping {
+        quoted.extend_from_slice(b"'$'");
+        *escaping = true;
+    }
+    for &byte in bytes {
+        match byte {
+            b'\x07' => quoted.extend_from_slice(b"\\a"),
+            b'\x08' => quoted.extend_from_slice(b"\\b"),
+            b'\t' => quoted.extend_from_slice(b"\\t"),
+            b'\n' => quoted.extend_from_slice(b"\\n"),
+            b'\x0b' => quoted.extend_from_slice(b"\\v"),
+            b'\x0c' => quoted.extend_from_slice(b"\\f"),
+            b'\r' => quoted.extend_from_slice(b"\\r"),
+            _ => quoted.extend_from_slice(format!("\\{:03o}", byte).as_bytes()),
+        }
+    }
+}
+
+/// When to print a line with the totals of all the files.
+#[derive(PartialEq)]
+enum Total {
+    /// Only when counting more than one file.
+    Auto,
+    Always,
+    /// Instead of the counts for each file.
+    Only,
+    Never,
+}
+
+/// What has to be counted, which decides how it's done: only bytes
+/// and newlines can be counted without decoding the input, and the
+/// number of bytes alone can often be had without reading it at all.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Bytes,
+    Lines,
+    All,
+}
+
+/// Regular files are split into chunks of at least this many bytes
+/// to be counted in parallel.
+const CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// A piece of counting for the worker threads to do: a whole file, or
+/// the part of one from `range.0` up to `range.1`.
+struct Job {
+    /// Index of the FILE operand.
+    file: usize,
+    /// Index of the chunk within the file.
+    chunk: usize,
+    input: Arc<Input>,
+    range: Option<(u64, u64)>,
+}
+
+impl Job {
+    fn run(&self, mode: Mode) -> io::Result<Counter> {
+        match (self.range, mode) {
+            (None, Mode::Bytes) => count_bytes(&self.input),
+            (None, Mode::Lines) => count_lines(reader(&self.input)),
+            (None, Mode::All) => count_all(reader(&self.input)),
+            (Some((start, end)), Mode::Lines) => count_lines(range_reader(&self.input, start, end)),
+            (Some((start, end)), _) => count_all(range_reader(&self.input, start, end)),
+        }
+    }
+}
+
+/// Splits the rest of `file` into chunks to be counted in parallel,
+/// if it's big enough to be worth it, seeking to its end as if it had
+/// been read. Boundaries are moved past any bytes that continue a
+/// UTF-8 character, so that no character is split between chunks.
+fn chunks(mut file: &File, mode: Mode, threads: usize) -> io::Result<Option<Vec<(u64, u64)>>> {
+    let metadata = file.metadata()?;
+    if mode == Mode::Bytes || threads < 2 || !metadata.is_file() {
+        return Ok(None);
+    }
+    let start = file.stream_position()?;
+    let end = metadata.size();
+    let count = (end.saturating_sub(start) / CHUNK_SIZE).min(threads as u64);
+    if count < 2 {
+        return Ok(None);
+    }
+
+    let mut boundaries = vec![start];
+    for i in 1..count {
+        let mut boundary = start + (end - start) / count * i;
+        let mut buf = [0; 64];
+        'scan: loop {
+            let n = file.read_at(&mut buf, boundary)?;
+            if n == 0 {
+                break;
+            }
+            for &b in &buf[..n] {
+                if b & 0xc0 != 0x80 {
+                    break 'scan;
+                }
+                boundary += 1;
+            }
+        }
+        boundaries.push(boundary.min(end));
+    }
+    boundaries.push(end);
+    file.seek(SeekFrom::Start(end))?;
+    Ok(Some(boundaries.windows(2).map(|w| (w[0], w[1])).collect()))
+}
+
+/// Reads `file` from its current offset on, retrying if interrupted.
+fn reader(mut file: &File) -> impl FnMut(&mut [u8]) -> io::Result<usize> + '_ {
+    move |buf| loop {
+        match file.read(buf) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            res => return res,
+        }
+    }
+}
+
+/// Reads `file` from `start` up to `end` without moving its offset,
+/// so that other threads can read other parts of it at the same time.
+fn range_reader(
+    file: &File,
+    mut start: u64,
+    end: u64,
+) -> impl FnMut(&mut [u8]) -> io::Result<usize> + '_ {
+    move |buf| {
+        let len = (end - start).min(buf.len() as u64) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        loop {
+            match file.read_at(&mut buf[..len], start) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                res => {
+                    start += *res.as_ref().unwrap_or(&0) as u64;
+                    return res;
+                }
+            }
+        }
+    }
+}
+
+/// Counts the bytes left in `file`. For a regular file this is found
+/// from its size, without reading it, as GNU wc does: only the end of
+/// one whose size is a multiple of the page size is read, since that
+/// is typical of files in /proc and /sys, whose sizes aren't to be
+/// trusted.
+fn count_bytes(mut file: &File) -> io::Result<Counter> {
     let mut bytes = 0;
-    let mut chars = 0;
-    let mut words = 0;
-    let mut newlines = 0;
-    let mut max_line = 0;
+    let metadata = file.metadata()?;
+    if metadata.is_file() {
+        let size = metadata.size();
+        let pos = file.stream_position()?;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let end = if size % page_size != 0 {
+            size
+        } else {
+            size - size % (metadata.blksize() + 1)
+        };
+        if pos < end {
+            file.seek(SeekFrom::Start(end))?;
+            bytes = end - pos;
+        }
+    }
 
+    let mut read = reader(file);
+    let mut buf = vec![0; BUF_SIZE];
     loop {
-        let mut buf = String::new();
-        let num_bytes = reader
-            .read_line(&mut buf)
-            .expect("Error reading text from file");
-        bytes += num_bytes;
-        if num_bytes == 0 {
-            // Reached EOF
-            break;
+        match read(&mut buf)? {
+            0 => break,
+            n => bytes += n as u64,
+        }
+    }
+    Ok(Counter {
+        stats: Stats {
+            bytes,
+            ..Stats::default()
+        },
+        ..Counter::default()
+    })
+}
+
+/// Counts the bytes and newlines given by `read`.
+fn count_lines<R: FnMut(&mut [u8]) -> io::Result<usize>>(mut read: R) -> io::Result<Counter> {
+    let mut counter = Counter::default();
+    let mut buf = vec![0; BUF_SIZE];
+    loop {
+        match read(&mut buf)? {
+            0 => break,
+            n => {
+                counter.stats.bytes += n as u64;
+                counter.stats.newlines += count_newlines(&buf[..n]) as u64;
+            }
         }
-        let mut in_word = false;
-        let mut line_length = 0;
-        for c in buf.chars() {
-            chars += 1;
-            line_length += 1;
-            let is_whitespace = c.is_whitespace();
-            if in_word && is_whitespace {
-                in_word = false;
-                words += 1;
+    }
+    Ok(counter)
+}
+/// Counts the newlines in `buf`, eight bytes at a time.
+fn count_newlines(buf: &[u8]) -> usize {
+    const LOW_BITS: u64 = 0x7f7f_7f7f_7f7f_7f7f;
+    const NEWLINES: u64 = 0x0a0a_0a0a_0a0a_0a0a;
+
+    let chunks = buf.chunks_exact(8);
+    let tail = chunks.remainder();
+    let mut count = 0;
+    for chunk in chunks {
+        // Bytes that are newlines become zero; the sum then sets the
+        // high bit of every byte that isn't zero, without carrying
+        // into the next one.
+        let word = u64::from_ne_bytes(chunk.try_into().unwrap()) ^ NEWLINES;
+        let nonzero = ((word & LOW_BITS) + LOW_BITS) | word;
+        count += (!(nonzero | LOW_BITS)).count_ones() as usize;
+    }
+    count + tail.iter().filter(|&&b| b == b'\n').count()
+}
+
+/// Computes and returns statistics for the text given by `read`,
+/// which is read until the EOF condition is reached.
+///
+/// The text is decoded as UTF-8. Bytes that aren't part of a valid
+/// character are counted as bytes but otherwise ignored.
+fn count_all<R: FnMut(&mut [u8]) -> io::Result<usize>>(mut read: R) -> io::Result<Counter> {
+    let mut counter = Counter::default();
+    let mut buf = vec![0; BUF_SIZE];
+    // The start of a character cut off at the end of the last read
+    // is kept at the start of the buffer.
+    let mut carried = 0;
+    loop {
+        let n = read(&mut buf[carried..])?;
+        counter.stats.bytes += n as u64;
+
+        let end = carried + n;
+        let mut data = &buf[..end];
+        while !data.is_empty() {
+            match str::from_utf8(data) {
+                Ok(text) => {
+                    counter.count(text);
+                    data = &[];
+                }
+                Err(err) => {
+                    let (valid, rest) = data.split_at(err.valid_up_to());
+                    // Safe: from_utf8 has just checked these bytes.
+                    counter.count(unsafe { str::from_utf8_unchecked(valid) });
+                    match err.error_len() {
+                        Some(len) => data = &rest[len..],
+                        None => {
+                            data = rest;
+                            break;
+                        }
+                    }
+                }
             }
-            if !in_word && !is_whitespace {
-                in_word = true;
+        }
+
+        // At the end of the input, what's left can't be completed.
+        if n == 0 {
+            break;
+        }
+        carried = data.len();
+        buf.copy_within(end - carried..end, 0);
+    }
+    Ok(counter)
+}
+
+/// The number of columns `c` takes up on a terminal, as wcwidth(3)
+/// has it: two for East Asian wide and fullwidth characters, none for
+/// combining marks and other zero-width characters, nor for control
+/// characters and noncharacters, which aren't printable.
+fn display_width(c: char) -> u64 {
+    let noncharacter = (c as u32 & 0xfffe) == 0xfffe || ('\u{fdd0}'..='\u{fdef}').contains(&c);
+    if noncharacter {
+        return 0;
+    }
+    c.width().unwrap_or(0) as u64
+}
+
+/// How a stretch of a line moves the column: `width` columns up to
+/// its first tab, if it has one, and `after_tab` columns past the tab
+/// stop that tab goes to.
+#[derive(Clone, Copy, Default)]
+struct Advance {
+    width: u64,
+    after_tab: Option<u64>,
+}
+
+impl Advance {
+    /// The column after the stretch, given the one it starts at.
+    fn apply(self, column: u64) -> u64 {
+        match self.after_tab {
+            None => column + self.width,
+            Some(rest) => (column + self.width) / 8 * 8 + 8 + rest,
+        }
+    }
+}
+
+/// The counts for some text, and where it left off.
+///
+/// The text may be a chunk from the middle of a file, so what it
+/// adds to the word count and to the maximum line width can depend on
+/// what came before it. The counts here are as if it started at the
+/// start of a line, outside a word, with what's needed to correct
+/// that kept alongside; `merge` puts the pieces together.
+#[derive(Default)]
+struct Counter {
+    /// The counts, with `max_line` only covering the lines that both
+    /// start and end within the text.
+    stats: Stats,
+    /// Whether the first character isn't whitespace, so that it may
+    /// continue a word from the text before.
+    starts_in_word: bool,
+    in_word: bool,
+    /// Whether the text has had a line break yet.
+    broken: bool,
+    /// How the text before its first line break moves the column, set
+    /// at that break.
+    prefix: Advance,
+    /// Before the first line break, the display width before the first
+    /// tab, once there is one.
+    first_tab: Option<u64>,
+    /// Display width of the current line so far; before the first line
+    /// break, past the first tab stop if there was a tab.
+    line_length: u64,
+}
+
+impl Counter {
+    /// Counts the characters of `text`.
+    ///
+    /// When reporting the number of characters, this does not take
+    /// into account "grapheme clusters," but it does consider
+    /// individual code points.
+    fn count(&mut self, text: &str) {
+        for c in text.chars() {
+            if self.stats.chars == 0 {
+                self.starts_in_word = !c.is_whitespace();
             }
-            if c == '\n' {
-                newlines += 1;
+            self.stats.chars += 1;
+            if c.is_whitespace() {
+                self.in_word = false;
+            } else if !self.in_word {
+                self.in_word = true;
+                self.stats.words += 1;
             }
-            if c.is_control() {
-                line_length -= 1;
-            } else if c == '\u{feff}' || c == '\u{fffe}' {
-                // These aren't printable...
-                line_length -= 1;
+            match c {
+                '\n' => {
+                    self.stats.newlines += 1;
+                    self.end_line();
+                }
+                // Like GNU wc, treat these as starting a new line for
+                // -L, as they do on a terminal.
+                '\r' | '\x0c' => self.end_line(),
+                '\t' if !self.broken && self.first_tab.is_none() => {
+                    self.first_tab = Some(self.line_length);
+                    self.line_length = 0;
+                }
+                '\t' => self.line_length += 8 - self.line_length % 8,
+                _ => self.line_length += display_width(c),
             }
         }
-        // consider end of line the end of the word
-        if in_word {
-            words += 1;
+    }
+
+    fn end_line(&mut self) {
+        if self.broken {
+            self.stats.max_line = self.stats.max_line.max(self.line_length);
+        } else {
+            self.prefix = self.advance();
+            self.broken = true;
         }
-        if line_length > max_line {
-            max_line = line_length;
+        self.line_length = 0;
+    }
+
+    /// How the text moves the column, if it has had no line break.
+    fn advance(&self) -> Advance {
+        match self.first_tab {
+            None => Advance {
+                width: self.line_length,
+                after_tab: None,
+            },
+            Some(width) => Advance {
+                width,
+                after_tab: Some(self.line_length),
+            },
         }
     }
+}
 
-    Stats {
-        bytes,
-        chars,
-        words,
-        newlines,
-        max_line,
+/// Puts together the counts for the consecutive pieces of a file.
+fn merge<I: IntoIterator<Item = Counter>>(counters: I) -> Stats {
+    let mut stats = Stats::default();
+    let mut in_word = false;
+    let mut column = 0;
+    for counter in counters {
+        stats.bytes += counter.stats.bytes;
+        stats.chars += counter.stats.chars;
+        stats.words += counter.stats.words;
+        stats.newlines += counter.stats.newlines;
+        // A word running on from the piece before was counted there.
+        if in_word && counter.starts_in_word {
+            stats.words -= 1;
+        }
+        if counter.stats.chars > 0 {
+            in_word = counter.in_word;
+        }
+
+        if counter.broken {
+            column = counter.prefix.apply(column);
+            stats.max_line = stats.max_line.max(column).max(counter.stats.max_line);
+            column = counter.line_length;
+        } else {
+            column = counter.advance().apply(column);
+        }
     }
+    stats.max_line = stats.max_line.max(column);
+    stats
 }
@@ -0,0 +1,47 @@
+//! FILE operands.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::os::unix::io::FromRawFd;
+
+/// A FILE operand, opened for reading.
+///
+/// Standard input is handled as a `File` like any other, so that
+/// when it is redirected from a regular file we can find its size
+/// and seek around in it. It is never closed, though.
+pub enum Input {
+    Stdin(ManuallyDrop<File>),
+    File(File),
+}
+
+impl Input {
+    pub fn stdin() -> Input {
+        Input::Stdin(ManuallyDrop::new(unsafe {
+            File::from_raw_fd(libc::STDIN_FILENO)
+        }))
+    }
+
+    /// Opens `filename`, or standard input if it's `-`.
+    pub fn open<P: AsRef<OsStr>>(filename: P) -> io::Result<Input> {
+        let filename = filename.as_ref();
+        if filename == "-" {
+            Ok(Input::stdin())
+        } else {
+            File::open(filename).map(Input::File)
+        }
+    }
+}
+
+impl Deref for Input {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        match self {
+            Input::Stdin(file) => file,
+            Input::File(file) => file,
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! Pieces shared by the utilities in `src/bin`.
+
+use std::io;
+use std::process;
+
+pub mod input;
+pub mod size;
+
+/// Reports a failure to write to standard output and exits. A closed
+/// pipe isn't worth a diagnostic; the reader simply went away.
+pub fn write_error(err: io::Error) -> ! {
+    if err.kind() != io::ErrorKind::BrokenPipe {
+        eprintln!("Error writing to standard output: {}", err);
+    }
+    process::exit(1);
+}
@@ -0,0 +1,107 @@
+//! Sizes and counts given on the command line, as GNU takes them.
+
+pub enum SizeError {
+    /// Not a number.
+    Invalid,
+    /// A number followed by something that isn't a suffix.
+    InvalidSuffix,
+    /// Doesn't fit in 64 bits once the suffix is applied.
+    Overflow,
+}
+
+/// Parses a number with an optional GNU-style multiplicative suffix:
+///
+///   b          512
+///   K, k       1024         KB  1000         KiB  1024
+///   M, m       1024^2       MB  1000^2       MiB  1024^2
+///   G          1024^3       GB  1000^3       GiB  1024^3
+///
+/// and so on through T, P, E, Z, Y, R and Q. As with GNU, a suffix
+/// on its own stands for one of that unit.
+///
+/// With `radix_prefixes`, as od has it, a number with a `0x` prefix is
+/// hexadecimal and one with a leading `0` octal; otherwise it's always
+/// decimal.
+pub fn parse_size(s: &str, radix_prefixes: bool) -> Result<u64, SizeError> {
+    let (radix, number) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) if radix_prefixes => (16, hex),
+        _ if radix_prefixes && s.starts_with('0') => (8, s),
+        _ => (10, s),
+    };
+    let digits_end = number
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(number.len());
+    let (digits, suffix) = number.split_at(digits_end);
+
+    let value = if digits.is_empty() {
+        match suffix.chars().next() {
+            _ if radix == 16 => return Err(SizeError::InvalidSuffix),
+            Some(c) if "bEGKkMmPQRTYZ".contains(c) => 1,
+            _ => return Err(SizeError::Invalid),
+        }
+    } else {
+        u64::from_str_radix(digits, radix).map_err(|_| SizeError::Overflow)?
+    };
+
+    let multiplier: u64 = match suffix {
+        "" => 1,
+        "b" => 512,
+        _ => {
+            let mut chars = suffix.chars();
+            let power = match chars.next() {
+                Some('K') | Some('k') => 1,
+                Some('M') | Some('m') => 2,
+                Some('G') => 3,
+                Some('T') => 4,
+                Some('P') => 5,
+                Some('E') => 6,
+                Some('Z') => 7,
+                Some('Y') => 8,
+                Some('R') => 9,
+                Some('Q') => 10,
+                _ => return Err(SizeError::InvalidSuffix),
+            };
+            let base: u64 = match chars.as_str() {
+                "" | "iB" => 1024,
+                "B" => 1000,
+                _ => return Err(SizeError::InvalidSuffix),
+            };
+            base.checked_pow(power).ok_or(SizeError::Overflow)?
+        }
+    };
+
+    value.checked_mul(multiplier).ok_or(SizeError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str, radix_prefixes: bool) -> Option<u64> {
+        parse_size(s, radix_prefixes).ok()
+    }
+
+    #[test]
+    fn suffixes() {
+        assert_eq!(parse("10", false), Some(10));
+        assert_eq!(parse("2b", false), Some(1024));
+        assert_eq!(parse("3k", false), Some(3 * 1024));
+        assert_eq!(parse("3KB", false), Some(3000));
+        assert_eq!(parse("1MiB", false), Some(1 << 20));
+        assert_eq!(parse("G", false), Some(1 << 30));
+        assert_eq!(parse("16E", false), None);
+        assert_eq!(parse("", false), None);
+        assert_eq!(parse("5x", false), None);
+        assert_eq!(parse("5KiBx", false), None);
+    }
+
+    #[test]
+    fn radix_prefixes() {
+        assert_eq!(parse("010", false), Some(10));
+        assert_eq!(parse("010", true), Some(8));
+        assert_eq!(parse("0x10", true), Some(16));
+        assert_eq!(parse("0x1k", true), Some(1024));
+        assert_eq!(parse("0x10", false), None);
+        assert_eq!(parse("09", true), None);
+    }
+}